@@ -1,18 +1,290 @@
-use crate::{ vehicle::Vehicle, physics::will_vehicles_collide };
+use std::{ cmp::Reverse, f32::consts::{ PI, TAU } };
 
-pub fn determine_velocity(car: &mut Vehicle, mut all_cars: Vec<Vehicle>) -> f32 {
-    let mut new_velocity: f32 = 3.0;
+use crate::{
+    conflict::ConflictTable,
+    vehicle::{ Vehicle, VehicleKind, RingStage, VEHICLE_WIDTH },
+    physics::{ will_vehicles_collide, queue_velocity_limit, is_kerb_hook },
+    layout::{ Junction, ring_angle },
+    platoon::{ platoon_head, platoon_velocity_limit },
+    schedule::slot_velocity_limit,
+    environment::command_velocity_limit,
+    geometry::Rect,
+    WINDOW_WIDTH,
+    WINDOW_HEIGHT,
+};
+
+// Rule deciding which of two conflicting cars goes first through a cross junction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Manager {
+    // The car closer to finishing goes first
+    DistanceToFinish,
+    // Conflict zones are granted in the order cars arrived at the stop line
+    Fifo,
+    // Cars close behind each other in a lane cross together, scheduled by their front car
+    Platoon,
+    // Entry times are planned together to keep the total delay lowest, and cars go in that order
+    Optimised,
+    // Cars bid for their slots from a budget, and go in the order the auction gives them
+    Auction,
+    // Cars follow the speeds and lane grants an outside controller sends, and only keep clear
+    // of cars from their own approach
+    External,
+}
+
+impl Manager {
+    pub fn from_name(name: &str) -> Option<Manager> {
+        match name {
+            "distance_to_finish" => Some(Manager::DistanceToFinish),
+            "fifo" => Some(Manager::Fifo),
+            "platoon" => Some(Manager::Platoon),
+            "optimised" => Some(Manager::Optimised),
+            "auction" => Some(Manager::Auction),
+            "external" => Some(Manager::External),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Manager::DistanceToFinish => "distance_to_finish",
+            Manager::Fifo => "fifo",
+            Manager::Platoon => "platoon",
+            Manager::Optimised => "optimised",
+            Manager::Auction => "auction",
+            Manager::External => "external",
+        }
+    }
+}
+
+pub fn determine_velocity(
+    car: &mut Vehicle,
+    mut all_cars: Vec<Vehicle>,
+    conflicts: &ConflictTable,
+    manager: Manager,
+    max_wait: Option<f32>,
+    tick: u64
+) -> f32 {
     car.colliding = false;
+    // Platoons are scheduled by members the conflict table may rule out
+    let everyone = all_cars.clone();
+    // Vehicles whose paths cannot meet this one's are never in its way
+    all_cars.retain(|c| conflicts.may_meet(car, c));
+
+    let queue_limit = queue_velocity_limit(car, &all_cars);
+
+    if must_yield_to_emergency(car, &all_cars) {
+        // Hold at the stop line until the emergency vehicle has passed
+        return (car.get_distance_to_stop_line() as f32)
+            .clamp(0.0, car.kind.max_speed())
+            .min(queue_limit);
+    }
+
+    if must_yield_to_cyclist(car, &all_cars) {
+        // Hold at the stop line until the bicycle on the inside has gone on
+        return (car.get_distance_to_stop_line() as f32)
+            .clamp(0.0, car.kind.max_speed())
+            .min(queue_limit);
+    }
+
+    if car.junction == Junction::Roundabout && must_give_way_on_entry(car, &all_cars) {
+        return (car.get_distance_to_stop_line() as f32)
+            .clamp(0.0, car.kind.max_speed())
+            .min(queue_limit);
+    }
+
+    let all_conflicting = all_cars.clone();
+    all_cars.retain(|c| goes_first(c, car, manager, max_wait, &everyone));
+
+    let velocity = avoid_collisions(car, all_cars).min(queue_limit);
+    match manager {
+        Manager::Platoon => velocity.min(platoon_velocity_limit(car, &everyone)),
+        Manager::Optimised | Manager::Auction => velocity.min(slot_velocity_limit(car, tick)),
+        Manager::Fifo => velocity.min(fifo_velocity_limit(car, &all_conflicting, conflicts)),
+        Manager::External => velocity.min(command_velocity_limit(car)),
+        _ => velocity,
+    }
+}
+
+// Traffic entering a roundabout waits at the give way line for any vehicle past its own that
+// it could run into. Both a quick and a slow getaway must be clear, as a waiting vehicle
+// pulls away slowly and cannot stop again once it is over the line.
+fn must_give_way_on_entry(car: &Vehicle, all_cars: &[Vehicle]) -> bool {
+    if car.ring_stage() != RingStage::Approaching {
+        return false;
+    }
+    all_cars
+        .iter()
+        .filter(|c| c.id != car.id && c.ring_stage() > RingStage::Approaching)
+        .filter(|c| !c.is_in_end_lane())
+        .any(|c| {
+            [1.0, car.kind.max_speed()].iter().any(|&velocity| {
+                let mut proceeding = car.clone();
+                proceeding.set_velocity(velocity);
+                will_vehicles_collide(&proceeding, c)
+            })
+        })
+}
 
-    //give priority to cars closer to finishing
-    all_cars.retain(
-        |c|
-            !c.is_in_end_lane() &&
-            c.position != car.position &&
-            c.get_distance_to_finish() <= car.get_distance_to_finish()
+// Vehicles further through the roundabout go first, on the ring the one further round
+fn has_right_of_way_on_roundabout(other_car: &Vehicle, car: &Vehicle) -> bool {
+    if other_car.is_in_end_lane() || other_car.id == car.id {
+        return false;
+    }
+    let (other_stage, stage) = (other_car.ring_stage(), car.ring_stage());
+    match (other_car.kind == VehicleKind::Emergency, car.kind == VehicleKind::Emergency) {
+        (true, false) => true,
+        (false, true) => other_stage > RingStage::Approaching,
+        _ if other_stage != stage => other_stage > stage,
+        _ if stage == RingStage::Circulating => {
+            let (a, b) = (other_car.get_centre(), car.get_centre());
+            let turned = ring_angle(a.x as f32, a.y as f32) - ring_angle(b.x as f32, b.y as f32);
+            let ahead = (turned * car.driving_side.ring_turn()).rem_euclid(TAU);
+            ahead < PI
+        }
+        _ =>
+            (other_car.get_distance_to_finish(), other_car.id) <
+                (car.get_distance_to_finish(), car.id),
+    }
+}
+
+fn goes_first(
+    other_car: &Vehicle,
+    car: &Vehicle,
+    manager: Manager,
+    max_wait: Option<f32>,
+    all_cars: &[Vehicle]
+) -> bool {
+    let emergency = car.kind == VehicleKind::Emergency || other_car.kind == VehicleKind::Emergency;
+    match (car.junction, manager) {
+        (Junction::Roundabout, _) => has_right_of_way_on_roundabout(other_car, car),
+        // The controller is left to decide between approaches, overdue cars included, and
+        // cars on the same approach keep the order they arrived in
+        (Junction::Cross, Manager::External) =>
+            other_car.origin == car.origin && arrived_first(other_car, car),
+        _ if !emergency && (is_overdue(other_car, max_wait) || is_overdue(car, max_wait)) =>
+            overdue_goes_first(other_car, car, max_wait),
+        (Junction::Cross, Manager::DistanceToFinish) => has_priority_over(other_car, car),
+        (Junction::Cross, Manager::Fifo) => arrived_first(other_car, car),
+        (Junction::Cross, Manager::Platoon) => platoon_goes_first(other_car, car, all_cars),
+        (Junction::Cross, Manager::Optimised | Manager::Auction) => planned_first(other_car, car),
+    }
+}
+
+// A car delayed for longer than the maximum wait in milliseconds, which no manager but external
+// may let go on waiting behind cars that have waited less
+pub fn is_overdue(car: &Vehicle, max_wait: Option<f32>) -> bool {
+    max_wait.is_some_and(|max_wait| car.get_delay() > max_wait)
+}
+
+// Overdue cars go before the rest, except cars already committed to the box, and among
+// themselves the longest waiting goes first
+fn overdue_goes_first(other_car: &Vehicle, car: &Vehicle, max_wait: Option<f32>) -> bool {
+    if other_car.is_in_end_lane() || other_car.id == car.id {
+        return false;
+    }
+    match (is_overdue(other_car, max_wait), is_overdue(car, max_wait)) {
+        (true, false) => true,
+        (false, true) => other_car.is_in_intersection(),
+        _ =>
+            other_car.get_delay() > car.get_delay() ||
+                (other_car.get_delay() == car.get_delay() && other_car.id < car.id),
+    }
+}
+
+// Within a platoon the car in front goes first, between platoons the front cars still crossing
+// are compared as distance_to_finish would compare them
+fn platoon_goes_first(other_car: &Vehicle, car: &Vehicle, all_cars: &[Vehicle]) -> bool {
+    if other_car.is_in_end_lane() || other_car.id == car.id {
+        return false;
+    }
+    if other_car.platoon == car.platoon {
+        return other_car.travelled > car.travelled;
+    }
+    has_priority_over(platoon_head(other_car, all_cars), platoon_head(car, all_cars))
+}
+
+// A car has arrived once its front is this close to the stop line, about a car length, so cars
+// waiting at their lines are ordered by when they got there and not by how near they stopped
+pub const ARRIVAL_DISTANCE: i32 = VEHICLE_WIDTH as i32;
+
+// Cars that have arrived in the order they got there, then the rest by how close they are to
+// the stop line
+fn arrival_order(car: &Vehicle) -> (u64, i32, u32) {
+    (car.arrival.unwrap_or(u64::MAX), car.get_distance_to_stop_line(), car.id)
+}
+
+fn arrived_first(other_car: &Vehicle, car: &Vehicle) -> bool {
+    !other_car.is_in_end_lane() &&
+        other_car.id != car.id &&
+        arrival_order(other_car) < arrival_order(car)
+}
+
+// Under fifo a car is granted its conflict zones once every car that arrived before it and could
+// meet it will be through their shared zone before it gets there, and a car ahead in its own
+// lane that has turned off its path is out of the box. Until then it waits at the stop line,
+// and an emergency vehicle never waits.
+fn fifo_velocity_limit(car: &Vehicle, all_cars: &[Vehicle], conflicts: &ConflictTable) -> f32 {
+    let distance = car.get_distance_to_stop_line();
+    if car.junction != Junction::Cross || car.kind == VehicleKind::Emergency || distance < 0 {
+        return f32::MAX;
+    }
+    let granted = all_cars
+        .iter()
+        // Cars already in their end lane count too, as a turned car's back can still be in the box
+        .filter(|c| c.id != car.id && arrival_order(c) < arrival_order(car))
+        .all(|c| {
+            if c.origin == car.origin && c.lane == car.lane {
+                leaves_lane_clear(c)
+            } else {
+                clears_zone_first(c, car, conflicts)
+            }
+        });
+    if granted { f32::MAX } else { distance as f32 }
+}
+
+// Slowest a car past its stop line is made to go, as nothing stops it inside the box
+const CRAWL_VELOCITY: f32 = 1.0;
+
+// Whether the other car, even crawling, leaves the zone it shares with this one before this
+// one could reach it at full speed. A car still short of its stop line may yet be held there.
+fn clears_zone_first(other_car: &Vehicle, car: &Vehicle, conflicts: &ConflictTable) -> bool {
+    match conflicts.zone(car.movement(), other_car.movement()) {
+        Some(zone) if other_car.get_distance_to_stop_line() < 0 => {
+            let other_left = zone.other_exit.saturating_sub(other_car.travelled) as f32;
+            let left = zone.entry.saturating_sub(car.travelled) as f32;
+            other_left / CRAWL_VELOCITY < left / car.kind.max_speed()
+        }
+        _ => false,
+    }
+}
+
+// A car ahead in the same lane is followed by the queue limit while it faces the same way,
+// but not once it turns, so a turning one has to be out of the box first
+fn leaves_lane_clear(ahead: &Vehicle) -> bool {
+    let junction_box = Rect::new(
+        (WINDOW_WIDTH / 3) as i32,
+        (WINDOW_HEIGHT / 3) as i32,
+        WINDOW_WIDTH / 3,
+        WINDOW_HEIGHT / 3
     );
+    ahead.get_distance_to_stop_line() >= 0 ||
+        ahead.direction == ahead.origin.opposite() ||
+        !ahead.get_rect().has_intersection(junction_box)
+}
+
+// Cars by their planned slot, with cars the plan has not reached yet last as fifo has them
+fn plan_order(car: &Vehicle) -> (u64, u64, i32, u32) {
+    let (arrival, distance, id) = arrival_order(car);
+    (car.slot.unwrap_or(u64::MAX), arrival, distance, id)
+}
+
+fn planned_first(other_car: &Vehicle, car: &Vehicle) -> bool {
+    !other_car.is_in_end_lane() && other_car.id != car.id && plan_order(other_car) < plan_order(car)
+}
 
-    let mut cars_after = all_cars;
+fn avoid_collisions(car: &mut Vehicle, mut cars_after: Vec<Vehicle>) -> f32 {
+    let cruise_velocity = car.kind.max_speed();
+    let mut new_velocity: f32 = cruise_velocity;
 
     if !car.is_in_end_lane() {
         for other_car in &mut cars_after {
@@ -25,7 +297,8 @@ pub fn determine_velocity(car: &mut Vehicle, mut all_cars: Vec<Vehicle>) -> f32
                 // Check again for collision with the updated velocity
                 if new_velocity == 1.0 {
                     if will_vehicles_collide(car, other_car) {
-                        new_velocity = 4.0;
+                        // Try to clear the way by speeding past instead
+                        new_velocity = cruise_velocity + 1.0;
                         car.set_velocity(new_velocity);
                         if will_vehicles_collide(car, other_car) {
                             new_velocity = 1.0;
@@ -40,3 +313,192 @@ pub fn determine_velocity(car: &mut Vehicle, mut all_cars: Vec<Vehicle>) -> f32
     }
     new_velocity
 }
+
+//give priority to cars closer to finishing, except that emergency vehicles go first
+pub fn has_priority_over(other_car: &Vehicle, car: &Vehicle) -> bool {
+    if other_car.is_in_end_lane() || other_car.position == car.position {
+        return false;
+    }
+    match (other_car.kind == VehicleKind::Emergency, car.kind == VehicleKind::Emergency) {
+        (true, false) => true,
+        // Emergency vehicles still wait for cars that are already committed to the box
+        (false, true) => other_car.is_in_intersection(),
+        // Turning traffic gives way to bicycles beside it
+        _ if is_kerb_hook(car, other_car) => true,
+        _ if is_kerb_hook(other_car, car) => false,
+        _ => other_car.get_distance_to_finish() <= car.get_distance_to_finish(),
+    }
+}
+
+// Cars that have not entered the box yet give way to any emergency vehicle they would cross
+fn must_yield_to_emergency(car: &Vehicle, all_cars: &[Vehicle]) -> bool {
+    if car.kind == VehicleKind::Emergency || !car.is_approaching() {
+        return false;
+    }
+
+    // Judge by the car's cruising speed so a car already holding is not released too early
+    let mut proceeding = car.clone();
+    proceeding.set_velocity(car.kind.max_speed());
+
+    all_cars
+        .iter()
+        .any(
+            |c|
+                c.kind == VehicleKind::Emergency &&
+                c.id != car.id &&
+                !c.is_in_end_lane() &&
+                will_vehicles_collide(&proceeding, c)
+        )
+}
+
+// Vehicles turning along the kerb wait before the box for a bicycle going straight on beside
+// them. Both a quick and a slow getaway must be clear, as a held vehicle pulls away slowly.
+fn must_yield_to_cyclist(car: &Vehicle, all_cars: &[Vehicle]) -> bool {
+    if !car.is_approaching() {
+        return false;
+    }
+    all_cars.iter().any(|c| {
+        [1.0, car.kind.max_speed()].iter().any(|&velocity| {
+            let mut proceeding = car.clone();
+            proceeding.set_velocity(velocity);
+            is_kerb_hook(&proceeding, c)
+        })
+    })
+}
+
+// Order in which determine_velocity lets cars claim the intersection, highest priority first
+pub fn priority_order(
+    all_cars: &[Vehicle],
+    manager: Manager,
+    max_wait: Option<f32>
+) -> Vec<&Vehicle> {
+    let mut ordered: Vec<&Vehicle> = all_cars
+        .iter()
+        .filter(|c| !c.is_in_end_lane())
+        .collect();
+    match manager {
+        Manager::DistanceToFinish => {
+            ordered.sort_by_key(|c| (c.kind != VehicleKind::Emergency, c.get_distance_to_finish()));
+        }
+        Manager::Fifo | Manager::External => {
+            ordered.sort_by_key(|c| arrival_order(c));
+        }
+        Manager::Optimised | Manager::Auction => {
+            ordered.sort_by_key(|c| plan_order(c));
+        }
+        Manager::Platoon => {
+            ordered.sort_by_key(|c| {
+                let head = platoon_head(c, all_cars);
+                (
+                    head.kind != VehicleKind::Emergency,
+                    head.get_distance_to_finish(),
+                    head.platoon,
+                    Reverse(c.travelled),
+                )
+            });
+        }
+    }
+    // Overdue cars move to the front, longest waiting first, the rest keeping their order
+    ordered.sort_by(|a, b| {
+        let overdue_key = |c: &Vehicle| {
+            if is_overdue(c, max_wait) { -c.get_delay() } else { f32::INFINITY }
+        };
+        overdue_key(a).total_cmp(&overdue_key(b))
+    });
+    ordered
+}
+
+// Pairs of (car, higher priority car) that determine_velocity would have to resolve
+pub fn conflicting_pairs<'a>(
+    all_cars: &'a [Vehicle],
+    conflicts: &ConflictTable,
+    manager: Manager,
+    max_wait: Option<f32>
+) -> Vec<(&'a Vehicle, &'a Vehicle)> {
+    let mut pairs = vec![];
+    for car in all_cars.iter().filter(|c| !c.is_in_end_lane()) {
+        for other_car in all_cars
+            .iter()
+            .filter(|c| {
+                goes_first(c, car, manager, max_wait, all_cars) && conflicts.may_meet(car, c)
+            }) {
+            if will_vehicles_collide(car, other_car) {
+                pairs.push((car, other_car));
+            }
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        environment::{ Command, Environment },
+        intersection::Direction,
+        layout::LaneLayout,
+    };
+
+    // Seeds that crashed when fifo trusted other cars to keep their speed and ignored its own lane
+    #[test]
+    fn fifo_runs_cars_through_without_crashing() {
+        for seed in [1, 3] {
+            let mut config = Config::new();
+            config.manager = Manager::Fifo;
+            config.seed = Some(seed);
+            config.spawn_interval_ticks = 30;
+            let mut environment = Environment::new(&config);
+            while !environment.step(&Command::new()).done {}
+            let crashes: u32 = environment.network.intersections
+                .iter()
+                .map(|intersection| intersection.live_crashes())
+                .sum();
+            assert_eq!(crashes, 0, "seed {}", seed);
+        }
+    }
+
+    // A car turning right along the kerb waits at its stop line while a bicycle beside it goes
+    // straight on or turns the same way, and goes once the bicycle is out of the way
+    #[test]
+    fn turning_car_waits_for_bicycle_on_the_inside() {
+        let layout = LaneLayout::new();
+        let conflicts = ConflictTable::new(&layout);
+        let (origin, direction) = (Direction::North, Direction::West);
+        let mut car = Vehicle::new_in_lane(origin, direction, VehicleKind::Car, &layout, 0, 0);
+        car.position.y = (WINDOW_HEIGHT as i32) / 3 - (car.height as i32);
+        car.set_velocity(VehicleKind::Car.max_speed());
+        assert_eq!(car.get_distance_to_stop_line(), 0);
+
+        let kind = VehicleKind::Bicycle;
+        for bicycle_direction in [Direction::South, Direction::West] {
+            let mut bicycle = Vehicle::new_in_lane(origin, bicycle_direction, kind, &layout, 0, 1);
+            bicycle.position.y = car.position.y + (car.height as i32) / 2;
+            bicycle.set_velocity(kind.max_speed());
+            let all_cars = vec![car.clone(), bicycle];
+            let velocity = determine_velocity(
+                &mut car,
+                all_cars,
+                &conflicts,
+                Manager::DistanceToFinish,
+                None,
+                0
+            );
+            assert_eq!(velocity, 0.0, "bicycle heading {:?}", bicycle_direction);
+        }
+
+        let mut bicycle = Vehicle::new_in_lane(origin, Direction::South, kind, &layout, 0, 1);
+        bicycle.position.y = (WINDOW_HEIGHT as i32) * 2 / 3;
+        bicycle.set_velocity(kind.max_speed());
+        let all_cars = vec![car.clone(), bicycle];
+        let velocity = determine_velocity(
+            &mut car,
+            all_cars,
+            &conflicts,
+            Manager::DistanceToFinish,
+            None,
+            0
+        );
+        assert!(velocity > 0.0);
+    }
+}
@@ -0,0 +1,63 @@
+use rand::Rng;
+
+use crate::{
+    conflict::ConflictTable,
+    vehicle::Vehicle,
+    schedule::plan_entries_by_rank,
+};
+
+// Credits every vehicle arrives with to bid from
+pub const STARTING_BUDGET: f32 = 100.0;
+// Range of credits per second of delay that vehicles would pay to avoid
+const MIN_VALUE_OF_TIME: f32 = 1.0;
+const MAX_VALUE_OF_TIME: f32 = 10.0;
+
+pub fn draw_value_of_time<R: Rng>(rng: &mut R) -> f32 {
+    rng.gen_range(MIN_VALUE_OF_TIME..=MAX_VALUE_OF_TIME)
+}
+
+// A vehicle's sealed bid is what the delay so far plus another second is worth to it, as much
+// as its budget allows, so vehicles that have waited bid higher
+pub fn bid(car: &Vehicle) -> f32 {
+    (car.value_of_time * (car.get_delay() / 1000.0 + 1.0)).min(car.budget)
+}
+
+// One auction round over the vehicles nearest the stop line. Slots go out in order of bid,
+// highest first, with the same zone limits as the optimised plan, and each vehicle is quoted
+// the highest bid it beat among the vehicles it could meet, never more than its own bid, to
+// pay when it enters. Returns the planned delay in ticks.
+pub fn run_auction(
+    vehicles: &mut [Vehicle],
+    conflicts: &ConflictTable,
+    max_wait: Option<f32>,
+    tick: u64
+) -> f32 {
+    let delay = plan_entries_by_rank(vehicles, conflicts, max_wait, tick, bid);
+
+    let prices: Vec<f32> = vehicles
+        .iter()
+        .map(|car| {
+            let slot = match car.slot {
+                Some(slot) if car.get_distance_to_stop_line() >= 0 => slot,
+                _ => {
+                    return 0.0;
+                }
+            };
+            vehicles
+                .iter()
+                .filter(|other| other.slot.is_some_and(|other_slot| other_slot > slot))
+                .filter(|other| other.get_distance_to_stop_line() >= 0)
+                // Vehicles behind it in its lane could never have gone first
+                .filter(|other| other.origin != car.origin || other.lane != car.lane)
+                .filter(|other| conflicts.may_meet(car, other))
+                .map(bid)
+                .fold(0.0, f32::max)
+                // A vehicle held back by its lane can outbid one it did not beat
+                .min(bid(car))
+        })
+        .collect();
+    for (car, price) in vehicles.iter_mut().zip(prices) {
+        car.price = price;
+    }
+    delay
+}
@@ -0,0 +1,131 @@
+// The simulation without a window, which builds without SDL2:
+// cargo run --no-default-features --bin smart-road-headless
+// A configured controller drives it for as many episodes as it asks for, otherwise one episode
+// is left to the configured manager as a baseline for controllers trained against it.
+// --compare platoon,distance_to_finish instead plays the same episode under each manager listed.
+use smart_road::{
+    config::{ Config, CONFIG_PATH },
+    environment::{ Command, Environment },
+    controller::{ Controller, Outcome, Reply },
+    algorithm::Manager,
+};
+
+fn main() -> Result<(), String> {
+    let mut config = Config::load(CONFIG_PATH)?;
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(position) = args.iter().position(|arg| arg == "--compare") {
+        let names = args
+            .get(position + 1)
+            .ok_or("--compare needs managers to compare, such as platoon,distance_to_finish")?;
+        return compare(&mut config, names);
+    }
+    let mut environment = Environment::new(&config);
+    if config.controller.address.is_some() {
+        return run_controlled(&config, &mut environment);
+    }
+    let mut total_reward = 0.0;
+    loop {
+        let step = environment.step(&Command::new());
+        total_reward += step.reward;
+        if step.done {
+            break;
+        }
+    }
+    report(&environment, total_reward);
+    Ok(())
+}
+
+// The first episode starts from the configured seed and every reset from the controller's.
+// Answering the end of an episode with a command, or disconnecting then, ends the run.
+fn run_controlled(config: &Config, environment: &mut Environment) -> Result<(), String> {
+    let mut controller = Controller::listen(&config.controller, &environment.network, true)?;
+    let mut outcome = Outcome { reward: 0.0, done: false };
+    let mut total_reward = 0.0;
+    loop {
+        let reply = match controller.exchange(&environment.network, Some(outcome)) {
+            Ok(reply) => reply,
+            Err(_) if outcome.done => {
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        };
+        match reply {
+            Reply::Reset(seed) => {
+                environment.reset(seed);
+                outcome = Outcome { reward: 0.0, done: false };
+                total_reward = 0.0;
+            }
+            Reply::Command(_) if outcome.done => {
+                return Ok(());
+            }
+            Reply::Command(command) => {
+                let step = environment.step(&command);
+                total_reward += step.reward;
+                outcome = Outcome { reward: step.reward, done: step.done };
+                if step.done {
+                    report(environment, total_reward);
+                }
+            }
+        }
+    }
+}
+
+// One episode from the configured seed, or 0 without one, under each manager. The spawns and
+// their routes come out the same every time, so only the manager makes the difference.
+fn compare(config: &mut Config, names: &str) -> Result<(), String> {
+    let mut managers = vec![];
+    for name in names.split(',').map(|n| n.trim()) {
+        managers.push(Manager::from_name(name).ok_or(format!("unknown manager '{}'", name))?);
+    }
+    println!(
+        "seed {}, {} ticks, a vehicle every {} ticks",
+        config.seed.unwrap_or(0),
+        config.episode_ticks,
+        config.spawn_interval_ticks
+    );
+    for manager in managers {
+        config.manager = manager;
+        let mut environment = Environment::new(config);
+        while !environment.step(&Command::new()).done {}
+        for (index, intersection) in environment.network.intersections.iter().enumerate() {
+            let stats = &intersection.stats;
+            let (vehicles, delay) = stats.movement_delays
+                .iter()
+                .fold((0, 0.0), |(vehicles, delay), (_, v, d)| (vehicles + v, delay + d));
+            println!(
+                "{} intersection {}: {:.1} vehicles a minute, {:.1} seconds mean delay, \
+                 {} close calls, {} crashes",
+                manager.name(),
+                index,
+                stats.throughput_per_minute(intersection.tick),
+                if vehicles > 0 { delay / (vehicles as f32) / 1000.0 } else { 0.0 },
+                intersection.live_close_calls(),
+                intersection.live_crashes()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn report(environment: &Environment, total_reward: f32) {
+    let observation = environment.observe();
+    println!(
+        "{} ticks, {} vehicles still in view, reward {:.1}",
+        observation.tick,
+        observation.vehicles.len(),
+        total_reward
+    );
+    for (index, intersection) in environment.network.intersections.iter().enumerate() {
+        let stats = &intersection.stats;
+        println!(
+            "intersection {}: {} vehicles, {} close calls, {} crashes, max wait {:.1} seconds",
+            index,
+            stats.num_vehicles,
+            intersection.live_close_calls(),
+            intersection.live_crashes(),
+            stats.max_wait / 1000.0
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::{ fs, time::{ SystemTime, UNIX_EPOCH } };
+
+use image::RgbImage;
+use sdl2::{ render::WindowCanvas, pixels::PixelFormatEnum };
+
+const CAPTURE_DIRECTORY: &str = "captures";
+
+pub struct FrameCapture {
+    pub screenshot_requested: bool,
+    pub every_nth_frame: u32,
+    pub sequence_directory: Option<String>,
+    frame_count: u64,
+    sequence_index: u32,
+}
+
+impl FrameCapture {
+    pub fn new(every_nth_frame: u32) -> Self {
+        Self {
+            screenshot_requested: false,
+            every_nth_frame: every_nth_frame.max(1),
+            sequence_directory: None,
+            frame_count: 0,
+            sequence_index: 0,
+        }
+    }
+
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.sequence_directory.is_some()
+    }
+
+    // Each recording goes to its own directory so earlier sequences are never overwritten
+    pub fn toggle_sequence(&mut self) -> Result<(), String> {
+        if self.is_recording() {
+            self.sequence_directory = None;
+            return Ok(());
+        }
+        let directory = format!("{}/sequence_{}", CAPTURE_DIRECTORY, unix_time());
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        self.sequence_directory = Some(directory);
+        self.sequence_index = 0;
+        Ok(())
+    }
+
+    // Must be called after drawing and before the canvas is presented. Recording stops if a
+    // frame fails to save.
+    pub fn capture(&mut self, canvas: &WindowCanvas) -> Result<(), String> {
+        self.frame_count += 1;
+
+        if self.screenshot_requested {
+            self.screenshot_requested = false;
+            fs::create_dir_all(CAPTURE_DIRECTORY).map_err(|e| e.to_string())?;
+            let path = format!(
+                "{}/screenshot_{}_{}.png",
+                CAPTURE_DIRECTORY,
+                unix_time(),
+                self.frame_count
+            );
+            save_canvas(canvas, &path)?;
+        }
+
+        if let Some(directory) = &self.sequence_directory {
+            if self.frame_count.is_multiple_of(self.every_nth_frame as u64) {
+                let path = format!("{}/frame_{:05}.png", directory, self.sequence_index);
+                // A frame that cannot be saved ends the recording rather than leaving a gap
+                if let Err(e) = save_canvas(canvas, &path) {
+                    self.sequence_directory = None;
+                    return Err(e);
+                }
+                self.sequence_index += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn save_canvas(canvas: &WindowCanvas, path: &str) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let pixels = canvas.read_pixels(None, PixelFormatEnum::RGB24)?;
+    let image = RgbImage::from_raw(width, height, pixels).ok_or(
+        format!("could not build image for {}", path)
+    )?;
+    image.save(path).map_err(|e| e.to_string())
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,496 @@
+use std::fs;
+#[cfg(feature = "gui")]
+use std::{ collections::HashMap, time::Duration };
+
+#[cfg(feature = "gui")]
+use sdl2::keyboard::Keycode;
+
+use crate::{
+    vehicle::{ VehicleKind, VehicleMix },
+    pedestrian::PedestrianDemand,
+    network::NetworkLayout,
+    routing::RouteCost,
+    layout::{ DrivingSide, Junction, LaneLayout, LaneUse },
+    intersection::Direction,
+    algorithm::Manager,
+    controller::ControllerSettings,
+    SPAWN_INTERVAL_TICKS,
+};
+
+pub const CONFIG_PATH: &str = "smart-road.cfg";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    SpawnFromNorth,
+    SpawnFromSouth,
+    SpawnFromEast,
+    SpawnFromWest,
+    SpawnEmergency,
+    ToggleContinuousSpawn,
+    Pause,
+    Step,
+    SlowDown,
+    SpeedUp,
+    ToggleHud,
+    ToggleDebug,
+    CycleHeatmap,
+    ExportHeatmap,
+    Screenshot,
+    ToggleRecording,
+    Quit,
+}
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        match name {
+            "spawn_from_north" => Some(Action::SpawnFromNorth),
+            "spawn_from_south" => Some(Action::SpawnFromSouth),
+            "spawn_from_east" => Some(Action::SpawnFromEast),
+            "spawn_from_west" => Some(Action::SpawnFromWest),
+            "spawn_emergency" => Some(Action::SpawnEmergency),
+            "toggle_continuous_spawn" => Some(Action::ToggleContinuousSpawn),
+            "pause" => Some(Action::Pause),
+            "step" => Some(Action::Step),
+            "slow_down" => Some(Action::SlowDown),
+            "speed_up" => Some(Action::SpeedUp),
+            "toggle_hud" => Some(Action::ToggleHud),
+            "toggle_debug" => Some(Action::ToggleDebug),
+            "cycle_heatmap" => Some(Action::CycleHeatmap),
+            "export_heatmap" => Some(Action::ExportHeatmap),
+            "screenshot" => Some(Action::Screenshot),
+            "toggle_recording" => Some(Action::ToggleRecording),
+            "quit" => Some(Action::Quit),
+            _ => None,
+        }
+    }
+}
+
+// Key bindings are only read with a window, the rest of the config works without SDL
+#[cfg(feature = "gui")]
+pub struct Bindings {
+    pub keys: HashMap<Keycode, Action>,
+    pub key_press_interval: Duration,
+}
+
+#[cfg(feature = "gui")]
+impl Bindings {
+    pub fn new() -> Self {
+        let keys = HashMap::from([
+            (Keycode::Down, Action::SpawnFromNorth),
+            (Keycode::Up, Action::SpawnFromSouth),
+            (Keycode::Left, Action::SpawnFromEast),
+            (Keycode::Right, Action::SpawnFromWest),
+            (Keycode::E, Action::SpawnEmergency),
+            (Keycode::R, Action::ToggleContinuousSpawn),
+            (Keycode::P, Action::Pause),
+            (Keycode::N, Action::Step),
+            (Keycode::LeftBracket, Action::SlowDown),
+            (Keycode::RightBracket, Action::SpeedUp),
+            (Keycode::H, Action::ToggleHud),
+            (Keycode::D, Action::ToggleDebug),
+            (Keycode::M, Action::CycleHeatmap),
+            (Keycode::X, Action::ExportHeatmap),
+            (Keycode::S, Action::Screenshot),
+            (Keycode::V, Action::ToggleRecording),
+            (Keycode::Escape, Action::Quit),
+        ]);
+        Self {
+            keys,
+            key_press_interval: Duration::from_millis(0),
+        }
+    }
+
+    pub fn action_for(&self, keycode: Keycode) -> Option<Action> {
+        self.keys.get(&keycode).copied()
+    }
+
+    // Binding an action replaces all of its default keys, several keys can be comma separated
+    fn set(&mut self, entry: &Entry) -> Result<(), String> {
+        if entry.key == "key_press_interval_ms" {
+            let millis = entry.value.parse::<u64>().map_err(|e| entry.error(&e.to_string()))?;
+            self.key_press_interval = Duration::from_millis(millis);
+            return Ok(());
+        }
+
+        let action = Action::from_name(&entry.key).ok_or(
+            entry.error(&format!("unknown action '{}'", entry.key))
+        )?;
+        self.keys.retain(|_, a| *a != action);
+        for name in entry.value.split(',').map(|n| n.trim()) {
+            let keycode = Keycode::from_name(name).ok_or(
+                entry.error(&format!("unknown key '{}'", name))
+            )?;
+            self.keys.insert(keycode, action);
+        }
+        Ok(())
+    }
+}
+
+pub struct Config {
+    #[cfg(feature = "gui")]
+    pub bindings: Bindings,
+    pub vehicle_mix: VehicleMix,
+    pub pedestrian_demand: PedestrianDemand,
+    pub network: NetworkLayout,
+    pub lanes: LaneLayout,
+    pub manager: Manager,
+    // Milliseconds, None when cars may wait indefinitely
+    pub max_wait: Option<f32>,
+    // None draws a fresh seed every run
+    pub seed: Option<u64>,
+    // Length of a headless episode
+    pub episode_ticks: u64,
+    // Ticks between vehicles while spawning continuously, and in headless episodes
+    pub spawn_interval_ticks: u64,
+    pub controller: ControllerSettings,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "gui")]
+            bindings: Bindings::new(),
+            vehicle_mix: VehicleMix::new(),
+            pedestrian_demand: PedestrianDemand::new(),
+            network: NetworkLayout::new(),
+            lanes: LaneLayout::new(),
+            manager: Manager::DistanceToFinish,
+            max_wait: None,
+            seed: None,
+            episode_ticks: 3600,
+            spawn_interval_ticks: SPAWN_INTERVAL_TICKS,
+            controller: ControllerSettings::new(),
+        }
+    }
+
+    // Kinds left out of a [vehicle_mix] section are never spawned
+    fn set_vehicle_mix(&mut self, entry: &Entry, first: bool) -> Result<(), String> {
+        if first {
+            self.vehicle_mix.weights.clear();
+        }
+        let kind = VehicleKind::from_name(&entry.key).ok_or(
+            entry.error(&format!("unknown vehicle kind '{}'", entry.key))
+        )?;
+        let weight = entry.value.parse::<u32>().map_err(|e| entry.error(&e.to_string()))?;
+        self.vehicle_mix.weights.retain(|(k, _)| *k != kind);
+        self.vehicle_mix.weights.push((kind, weight));
+        Ok(())
+    }
+
+    fn set_pedestrians(&mut self, entry: &Entry) -> Result<(), String> {
+        match entry.key.as_str() {
+            "rate_per_minute" => {
+                self.pedestrian_demand.rate_per_minute = entry.value
+                    .parse::<f32>()
+                    .map_err(|e| entry.error(&e.to_string()))?;
+            }
+            _ => {
+                return Err(entry.error(&format!("unknown pedestrian setting '{}'", entry.key)));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_network(&mut self, entry: &Entry) -> Result<(), String> {
+        if entry.key == "routing" {
+            self.network.route_cost = RouteCost::from_name(&entry.value).ok_or(
+                entry.error(&format!("unknown routing '{}'", entry.value))
+            )?;
+            return Ok(());
+        }
+        if entry.key == "compare_junctions" {
+            self.network.compare_junctions = entry.value
+                .parse::<bool>()
+                .map_err(|e| entry.error(&e.to_string()))?;
+            return Ok(());
+        }
+
+        let value = entry.value.parse::<usize>().map_err(|e| entry.error(&e.to_string()))?;
+        match entry.key.as_str() {
+            "rows" => {
+                self.network.rows = value;
+            }
+            "columns" => {
+                self.network.columns = value;
+            }
+            "link_capacity" => {
+                self.network.link_capacity = value;
+            }
+            _ => {
+                return Err(entry.error(&format!("unknown network setting '{}'", entry.key)));
+            }
+        }
+        Ok(())
+    }
+
+    // Each approach lists its lanes from the kerb, such as "straight+right, left, left"
+    fn set_lanes(&mut self, entry: &Entry) -> Result<(), String> {
+        if entry.key == "junction" {
+            self.lanes.junction = Junction::from_name(&entry.value).ok_or(
+                entry.error(&format!("unknown junction '{}'", entry.value))
+            )?;
+            return Ok(());
+        }
+        if entry.key == "arms" {
+            let mut arms = vec![];
+            for name in entry.value.split(',').map(|n| n.trim()) {
+                let arm = Direction::from_name(name).ok_or(
+                    entry.error(&format!("unknown arm '{}'", name))
+                )?;
+                if !arms.contains(&arm) {
+                    arms.push(arm);
+                }
+            }
+            self.lanes.arms = arms;
+            return Ok(());
+        }
+        if entry.key == "driving_side" {
+            let driving_side = DrivingSide::from_name(&entry.value).ok_or(
+                entry.error(&format!("unknown driving side '{}'", entry.value))
+            )?;
+            self.lanes.set_driving_side(driving_side);
+            return Ok(());
+        }
+
+        let origin = Direction::from_name(&entry.key).ok_or(
+            entry.error(&format!("unknown approach '{}'", entry.key))
+        )?;
+        let mut lanes = vec![];
+        for name in entry.value.split(',').map(|n| n.trim()) {
+            let lane_use = LaneUse::from_name(name).ok_or(
+                entry.error(&format!("unknown lane use '{}'", name))
+            )?;
+            lanes.push(lane_use);
+        }
+        self.lanes.set_lanes(origin, lanes);
+        Ok(())
+    }
+
+    fn set_manager(&mut self, entry: &Entry) -> Result<(), String> {
+        match entry.key.as_str() {
+            "policy" => {
+                self.manager = Manager::from_name(&entry.value).ok_or(
+                    entry.error(&format!("unknown manager '{}'", entry.value))
+                )?;
+            }
+            // 0 turns the guarantee off
+            "max_wait_seconds" => {
+                let seconds = entry.value
+                    .parse::<f32>()
+                    .map_err(|e| entry.error(&e.to_string()))?;
+                self.max_wait = if seconds > 0.0 { Some(seconds * 1000.0) } else { None };
+            }
+            _ => {
+                return Err(entry.error(&format!("unknown manager setting '{}'", entry.key)));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_simulation(&mut self, entry: &Entry) -> Result<(), String> {
+        let value = entry.value.parse::<u64>().map_err(|e| entry.error(&e.to_string()))?;
+        match entry.key.as_str() {
+            "seed" => {
+                self.seed = Some(value);
+            }
+            "episode_ticks" => {
+                self.episode_ticks = value;
+            }
+            "spawn_interval_ticks" => {
+                self.spawn_interval_ticks = value;
+            }
+            _ => {
+                return Err(entry.error(&format!("unknown simulation setting '{}'", entry.key)));
+            }
+        }
+        Ok(())
+    }
+
+    // An empty address runs without a controller
+    fn set_controller(&mut self, entry: &Entry) -> Result<(), String> {
+        match entry.key.as_str() {
+            "address" => {
+                self.controller.address = if entry.value.is_empty() {
+                    None
+                } else {
+                    Some(entry.value.clone())
+                };
+            }
+            "lockstep" => {
+                self.controller.lockstep = entry.value
+                    .parse::<bool>()
+                    .map_err(|e| entry.error(&e.to_string()))?;
+            }
+            _ => {
+                return Err(entry.error(&format!("unknown controller setting '{}'", entry.key)));
+            }
+        }
+        Ok(())
+    }
+
+    // A missing file is not an error, the defaults are used instead
+    pub fn load(path: &str) -> Result<Config, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(path, &text),
+            Err(_) => Ok(Config::new()),
+        }
+    }
+
+    // Settings in the text override the defaults, errors name the path and line they are on
+    pub fn parse(path: &str, text: &str) -> Result<Config, String> {
+        let mut config = Config::new();
+        let mut mix_seen = false;
+        for entry in parse_entries(path, text)? {
+            match entry.section.as_str() {
+                #[cfg(feature = "gui")]
+                "bindings" => config.bindings.set(&entry)?,
+                // The same file serves the headless build, which has no keys to bind
+                #[cfg(not(feature = "gui"))]
+                "bindings" => {}
+                "vehicle_mix" => {
+                    config.set_vehicle_mix(&entry, !mix_seen)?;
+                    mix_seen = true;
+                }
+                "pedestrians" => config.set_pedestrians(&entry)?,
+                "network" => config.set_network(&entry)?,
+                "lanes" => config.set_lanes(&entry)?,
+                "manager" => config.set_manager(&entry)?,
+                "simulation" => config.set_simulation(&entry)?,
+                "controller" => config.set_controller(&entry)?,
+                _ => {
+                    return Err(entry.error(&format!("unknown section '{}'", entry.section)));
+                }
+            }
+        }
+        config.lanes.validate().map_err(|e| format!("{}: [lanes] {}", path, e))?;
+        Ok(config)
+    }
+}
+
+struct Entry {
+    path: String,
+    line: usize,
+    section: String,
+    key: String,
+    value: String,
+}
+
+impl Entry {
+    fn error(&self, message: &str) -> String {
+        format!("{}:{}: {}", self.path, self.line, message)
+    }
+}
+
+// INI style: [section] headers, key = value pairs and # comments
+fn parse_entries(path: &str, text: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = vec![];
+    let mut section = String::new();
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = raw_line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_string();
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value)) => {
+                entries.push(Entry {
+                    path: path.to_string(),
+                    line: index + 1,
+                    section: section.clone(),
+                    key: key.trim().to_string(),
+                    value: value.trim().to_string(),
+                });
+            }
+            None => {
+                return Err(format!("{}:{}: expected key = value", path, index + 1));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_config_parses() {
+        assert!(Config::parse(CONFIG_PATH, include_str!("../smart-road.cfg")).is_ok());
+    }
+
+    #[test]
+    fn parses_key_lists() {
+        let text = "
+            [lanes]
+            arms = north, east , west, east
+            north = straight+right, left
+            [vehicle_mix]
+            car = 3
+            bicycle = 1
+            car = 2
+        ";
+        let config = Config::parse("test.cfg", text).unwrap();
+        let lanes = &config.lanes;
+        assert_eq!(lanes.arms, vec![Direction::North, Direction::East, Direction::West]);
+        let shared = LaneUse { left: false, straight: true, right: true };
+        assert_eq!(lanes.approaches[0], vec![shared, LaneUse::LEFT]);
+        // Only the kinds listed are spawned, a kind listed twice keeps its last weight
+        assert_eq!(
+            config.vehicle_mix.weights,
+            vec![(VehicleKind::Bicycle, 1), (VehicleKind::Car, 2)]
+        );
+    }
+
+    #[cfg(feature = "gui")]
+    #[test]
+    fn binds_every_key_listed() {
+        let config = Config::parse("test.cfg", "[bindings]\npause = P, Space").unwrap();
+        assert_eq!(config.bindings.action_for(Keycode::P), Some(Action::Pause));
+        assert_eq!(config.bindings.action_for(Keycode::Space), Some(Action::Pause));
+        let error = Config::parse("test.cfg", "[bindings]\npause = P, Nope").err();
+        assert_eq!(error.as_deref(), Some("test.cfg:2: unknown key 'Nope'"));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        for (text, message) in [
+            ("[weather]\nrain = 1", "test.cfg:2: unknown section 'weather'"),
+            ("[manager]\nspeed = 3", "test.cfg:2: unknown manager setting 'speed'"),
+            ("[network]\n\nhops = 3", "test.cfg:3: unknown network setting 'hops'"),
+            ("[vehicle_mix]\ntram = 1", "test.cfg:2: unknown vehicle kind 'tram'"),
+            ("[lanes]\nup = left", "test.cfg:2: unknown approach 'up'"),
+            ("[simulation]\nseed", "test.cfg:2: expected key = value"),
+        ] {
+            assert_eq!(Config::parse("test.cfg", text).err().as_deref(), Some(message));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        for text in [
+            "[manager]\npolicy = fastest",
+            "[manager]\nmax_wait_seconds = soon",
+            "[network]\nrows = two",
+            "[network]\nrouting = scenic",
+            "[lanes]\nnorth = straight, sideways",
+            "[lanes]\narms = north, up",
+            "[lanes]\ndriving_side = middle",
+            "[simulation]\nseed = -1",
+            "[controller]\nlockstep = yes",
+            "[vehicle_mix]\ncar = lots",
+        ] {
+            let error = Config::parse("test.cfg", text).err();
+            let on_line = error.is_some_and(|e| e.starts_with("test.cfg:2: "));
+            assert!(on_line, "{:?} was not rejected at its line", text);
+        }
+
+        // Lanes are checked once the whole file has been read
+        let text = "[lanes]\narms = north, south";
+        let error = Config::parse("test.cfg", text).err();
+        assert_eq!(error.as_deref(), Some("test.cfg: [lanes] a junction needs at least 3 arms"));
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{
+    geometry::Rect,
+    intersection::Direction,
+    layout::{ Junction, LaneLayout },
+    vehicle::{ Vehicle, VehicleKind },
+    physics::{ are_within_safety_gap, predicted_footprint },
+};
+
+// Pixels of path merged into one footprint, coarse enough to keep the table quick to build
+const SEGMENT_LENGTH: usize = 8;
+// Longest path followed before giving up on a vehicle that never leaves the window
+const MAX_PATH_LENGTH: usize = 4000;
+
+// A lane's movement through the junction, the unit conflicts are worked out between
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Movement {
+    pub origin: Direction,
+    // Approach lane counted from the kerb
+    pub lane: usize,
+    pub direction: Direction,
+}
+
+// Where two movements' footprints come within the safety gap of each other, as pixels driven
+// since entering the window along each path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConflictZone {
+    pub entry: u32,
+    pub exit: u32,
+    pub other_entry: u32,
+    pub other_exit: u32,
+}
+
+impl ConflictZone {
+    // The same zone seen from the other path
+    pub fn swapped(&self) -> ConflictZone {
+        ConflictZone {
+            entry: self.other_entry,
+            exit: self.other_exit,
+            other_entry: self.entry,
+            other_exit: self.exit,
+        }
+    }
+}
+
+// Conflicts between every pair of lane movements a layout allows, worked out once by driving
+// each movement with every vehicle kind that can make it
+pub struct ConflictTable {
+    pub movements: Vec<Movement>,
+    // Indexed like movements on both sides, None where the paths never meet
+    zones: Vec<Vec<Option<ConflictZone>>>,
+}
+
+impl ConflictTable {
+    pub fn new(layout: &LaneLayout) -> Self {
+        let mut movements = vec![];
+        for &origin in &layout.arms {
+            for direction in layout.exits(origin) {
+                for lane in layout.legal_lanes(origin, direction) {
+                    movements.push(Movement { origin, lane, direction });
+                }
+            }
+        }
+
+        let paths: Vec<Vec<Rect>> = movements
+            .iter()
+            .map(|&movement| path_segments(layout, movement))
+            .collect();
+
+        let mut zones = vec![vec![None; movements.len()]; movements.len()];
+        for a in 0..movements.len() {
+            for b in a..movements.len() {
+                if let Some(zone) = conflict_zone(&paths[a], &paths[b]) {
+                    zones[a][b] = Some(zone);
+                    zones[b][a] = Some(zone.swapped());
+                }
+            }
+        }
+
+        Self { movements, zones }
+    }
+
+    fn index(&self, movement: Movement) -> Option<usize> {
+        self.movements.iter().position(|&m| m == movement)
+    }
+
+    // Zone on the first movement's path and then on the other's, None when they never meet
+    // or when either movement is not in the table
+    pub fn zone(&self, movement: Movement, other: Movement) -> Option<ConflictZone> {
+        match (self.index(movement), self.index(other)) {
+            (Some(a), Some(b)) => self.zones[a][b],
+            _ => None,
+        }
+    }
+
+    // Two vehicles can only meet while both are short of the end of their conflict zone.
+    // Movements outside the table, such as a bicycle riding straight on from a turning lane,
+    // are assumed to conflict with everything.
+    pub fn may_meet(&self, vehicle: &Vehicle, other: &Vehicle) -> bool {
+        let (movement, other_movement) = (vehicle.movement(), other.movement());
+        if self.index(movement).is_none() || self.index(other_movement).is_none() {
+            return true;
+        }
+        match self.zone(movement, other_movement) {
+            None => false,
+            Some(zone) => vehicle.travelled < zone.exit && other.travelled < zone.other_exit,
+        }
+    }
+
+    pub fn conflict_count(&self) -> usize {
+        self.zones
+            .iter()
+            .enumerate()
+            .map(|(a, row)| {
+                row.iter()
+                    .skip(a + 1)
+                    .filter(|zone| zone.is_some())
+                    .count()
+            })
+            .sum()
+    }
+}
+
+// Footprints along a movement's path merged every SEGMENT_LENGTH pixels, covering every kind
+// that can make the movement from its lane at once. Bicycles only ride the kerb lane.
+fn path_segments(layout: &LaneLayout, movement: Movement) -> Vec<Rect> {
+    let mut segments: Vec<Rect> = vec![];
+    let kerb_lane = movement.lane == 0 || layout.junction == Junction::Roundabout;
+    let kinds = VehicleKind::ALL.into_iter().filter(|kind| {
+        kind.can_make(movement.origin, movement.direction, layout.driving_side) &&
+            (kerb_lane || *kind != VehicleKind::Bicycle)
+    });
+    for kind in kinds {
+        let vehicle = Vehicle::new_in_lane(
+            movement.origin,
+            movement.direction,
+            kind,
+            layout,
+            movement.lane,
+            0
+        );
+        add_path_segments(vehicle, &mut segments);
+    }
+    segments
+}
+
+// Footprints along the rest of one vehicle's path, segments counted from where it is now
+pub fn remaining_path_segments(vehicle: &Vehicle) -> Vec<Rect> {
+    let mut segments: Vec<Rect> = vec![];
+    add_path_segments(vehicle.clone(), &mut segments);
+    segments
+}
+
+fn add_path_segments(mut vehicle: Vehicle, segments: &mut Vec<Rect>) {
+    vehicle.set_velocity(1.0);
+    for step in 0..MAX_PATH_LENGTH {
+        if !vehicle.is_in_entire_intersection() {
+            break;
+        }
+        let footprint = predicted_footprint(&vehicle, vehicle.position);
+        let segment = step / SEGMENT_LENGTH;
+        if segment < segments.len() {
+            segments[segment] = segments[segment].union(footprint);
+        } else {
+            segments.push(footprint);
+        }
+        vehicle.update(1.0);
+    }
+}
+
+// Where two paths of merged footprints come within the safety gap, in pixels along each
+pub fn conflict_zone(path: &[Rect], other_path: &[Rect]) -> Option<ConflictZone> {
+    let mut zone: Option<ConflictZone> = None;
+    for (a, &segment) in path.iter().enumerate() {
+        for (b, &other_segment) in other_path.iter().enumerate() {
+            if !are_within_safety_gap(segment, other_segment) {
+                continue;
+            }
+            let (start, end) = ((a * SEGMENT_LENGTH) as u32, ((a + 1) * SEGMENT_LENGTH) as u32);
+            let (other_start, other_end) = (
+                (b * SEGMENT_LENGTH) as u32,
+                ((b + 1) * SEGMENT_LENGTH) as u32,
+            );
+            zone = Some(match zone {
+                None =>
+                    ConflictZone {
+                        entry: start,
+                        exit: end,
+                        other_entry: other_start,
+                        other_exit: other_end,
+                    },
+                Some(zone) =>
+                    ConflictZone {
+                        entry: zone.entry.min(start),
+                        exit: zone.exit.max(end),
+                        other_entry: zone.other_entry.min(other_start),
+                        other_exit: zone.other_exit.max(other_end),
+                    },
+            });
+        }
+    }
+    zone
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movement(origin: Direction, lane: usize, direction: Direction) -> Movement {
+        Movement { origin, lane, direction }
+    }
+
+    #[test]
+    fn conflicts_between_default_lanes() {
+        let table = ConflictTable::new(&LaneLayout::new());
+        let (north, south, east, west) = (
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        );
+        // Default lanes from the kerb turn right, go straight on and turn left
+        let cases = [
+            ("opposite straights", (north, 1, south), (south, 1, north), false),
+            ("left turn and opposite straight", (north, 2, east), (south, 1, north), true),
+            ("crossing straights", (north, 1, south), (east, 1, west), true),
+            ("opposite right turns", (north, 0, west), (south, 0, east), false),
+            ("right turn and straight beside it", (south, 0, east), (south, 1, north), false),
+            ("straight and left turn beside it", (north, 1, south), (north, 2, east), false),
+            ("right and left turns from one approach", (south, 0, east), (south, 2, west), false),
+            // Turning west the footprint swings back across the lanes beside it
+            ("turn west beside a straight", (north, 0, west), (north, 1, south), true),
+        ];
+        for (name, a, b, meets) in cases {
+            let (a, b) = (movement(a.0, a.1, a.2), movement(b.0, b.1, b.2));
+            assert_eq!(table.zone(a, b).is_some(), meets, "{}", name);
+            assert_eq!(table.zone(b, a).is_some(), meets, "{} swapped", name);
+        }
+    }
+}
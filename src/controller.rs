@@ -0,0 +1,465 @@
+use std::{
+    io::{ self, BufRead, BufReader, ErrorKind, Read, Write },
+    net::{ SocketAddr, TcpListener, TcpStream },
+};
+// Unix sockets only exist on Unix, elsewhere a unix: address is refused when listening
+#[cfg(unix)]
+use std::{ fs, os::unix::{ fs::FileTypeExt, net::{ UnixListener, UnixStream } } };
+
+use crate::{
+    network::Network,
+    intersection::Direction,
+    environment::{ Command, LaneGrant },
+    json::{ Json, quote },
+};
+
+// Raised whenever a message changes in a way an older controller would misread,
+// see CONTROLLER_PROTOCOL.md
+pub const PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub struct ControllerSettings {
+    // A loopback address and port such as 127.0.0.1:7878, or unix: and a socket path
+    pub address: Option<String>,
+    // Wait for the controller's command before every tick instead of using its latest one
+    pub lockstep: bool,
+}
+
+impl ControllerSettings {
+    pub fn new() -> Self {
+        Self {
+            address: None,
+            lockstep: true,
+        }
+    }
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn try_clone(&self) -> io::Result<Connection> {
+        match self {
+            Connection::Tcp(stream) => stream.try_clone().map(Connection::Tcp),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.try_clone().map(Connection::Unix),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+// Reward for the tick just run and whether it ended the episode, sent with headless states
+#[derive(Debug, Clone, Copy)]
+pub struct Outcome {
+    pub reward: f32,
+    pub done: bool,
+}
+
+// What the simulation should do next, as the controller asked
+#[derive(Debug, Clone)]
+pub enum Reply {
+    Command(Command),
+    // Start a new episode from this seed
+    Reset(u64),
+}
+
+// An intersection manager running in another process. It is sent every vehicle's state each
+// tick as a line of JSON and answers with commands, one JSON line each.
+pub struct Controller {
+    reader: BufReader<Connection>,
+    writer: Connection,
+    lockstep: bool,
+    // Whether resets are taken, which only the headless binary does
+    episodes: bool,
+    // Part of a line that has arrived without its end yet
+    pending: Vec<u8>,
+    command: Command,
+    reset: Option<u64>,
+}
+
+impl Controller {
+    // Waits for a controller to connect, then greets it
+    pub fn listen(
+        settings: &ControllerSettings,
+        network: &Network,
+        episodes: bool
+    ) -> Result<Controller, String> {
+        let address = settings.address.as_deref().ok_or("no controller address")?;
+        println!("waiting for a controller on {}", address);
+        let connection = match address.strip_prefix("unix:") {
+            Some(path) => accept_unix(path)?,
+            None => {
+                let socket_address: SocketAddr = address
+                    .parse()
+                    .map_err(|_| format!("bad controller address '{}'", address))?;
+                if !socket_address.ip().is_loopback() {
+                    return Err(format!("controller address '{}' is not on localhost", address));
+                }
+                let listener = TcpListener::bind(socket_address).map_err(|e| e.to_string())?;
+                let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+                stream.set_nodelay(true).map_err(|e| e.to_string())?;
+                Connection::Tcp(stream)
+            }
+        };
+
+        let mut controller = Controller {
+            reader: BufReader::new(connection.try_clone().map_err(|e| e.to_string())?),
+            writer: connection,
+            lockstep: settings.lockstep,
+            episodes,
+            pending: vec![],
+            command: Command::new(),
+            reset: None,
+        };
+        controller.send(
+            &format!(
+                "{{\"type\":\"hello\",\"protocol\":{},\"manager\":{},\"lockstep\":{},\
+                 \"episodes\":{},\"intersections\":{}}}",
+                PROTOCOL_VERSION,
+                quote(network.focused().manager.name()),
+                controller.lockstep,
+                controller.episodes,
+                network.intersections.len()
+            )
+        )?;
+        Ok(controller)
+    }
+
+    // Sends the state for the coming tick and returns what to drive it with. In lockstep this
+    // waits for the controller's answer, otherwise the latest command that has arrived is used,
+    // or the one before when nothing new has come. The end of an episode is always waited for,
+    // as the controller decides whether another one follows.
+    pub fn exchange(
+        &mut self,
+        network: &Network,
+        outcome: Option<Outcome>
+    ) -> Result<Reply, String> {
+        self.send(&state_message(network, outcome))?;
+        if self.lockstep || outcome.is_some_and(|outcome| outcome.done) {
+            loop {
+                let line = self.read_line()?.ok_or("controller sent nothing")?;
+                if line.is_empty() {
+                    continue;
+                }
+                match self.parse_reply(&line) {
+                    Ok(Reply::Command(command)) => {
+                        self.command = command;
+                        break;
+                    }
+                    Ok(Reply::Reset(seed)) => {
+                        return Ok(Reply::Reset(seed));
+                    }
+                    Err(e) => self.send(&error_message(&e))?,
+                }
+            }
+        } else {
+            let mut errors = vec![];
+            self.reader.get_ref().set_nonblocking(true).map_err(|e| e.to_string())?;
+            let received = self.read_available(&mut errors);
+            self.reader.get_ref().set_nonblocking(false).map_err(|e| e.to_string())?;
+            received?;
+            for error in errors {
+                self.send(&error_message(&error))?;
+            }
+            if let Some(seed) = self.reset.take() {
+                return Ok(Reply::Reset(seed));
+            }
+        }
+        Ok(Reply::Command(self.command.clone()))
+    }
+
+    fn read_available(&mut self, errors: &mut Vec<String>) -> Result<(), String> {
+        while let Some(line) = self.read_line()? {
+            if line.is_empty() {
+                continue;
+            }
+            match self.parse_reply(&line) {
+                Ok(Reply::Command(command)) => {
+                    self.command = command;
+                }
+                Ok(Reply::Reset(seed)) => {
+                    self.reset = Some(seed);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_reply(&self, line: &str) -> Result<Reply, String> {
+        let reply = parse_reply(line)?;
+        if matches!(reply, Reply::Reset(_)) && !self.episodes {
+            return Err("'reset' only works with smart-road-headless".to_string());
+        }
+        Ok(reply)
+    }
+
+    // The next whole line, or None when the rest of it has not arrived yet
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        loop {
+            match self.reader.read_until(b'\n', &mut self.pending) {
+                Ok(_) if self.pending.ends_with(b"\n") => {
+                    let line = String::from_utf8_lossy(&self.pending).trim().to_string();
+                    self.pending.clear();
+                    return Ok(Some(line));
+                }
+                // The stream ended, possibly partway through a line
+                Ok(_) => {
+                    return Err("controller disconnected".to_string());
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    return Ok(None);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    return Err(e.to_string());
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, message: &str) -> Result<(), String> {
+        writeln!(self.writer, "{}", message)
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| format!("controller disconnected: {}", e))
+    }
+}
+
+#[cfg(unix)]
+fn accept_unix(path: &str) -> Result<Connection, String> {
+    // Only a socket left behind by an earlier run is removed
+    if fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    let listener = UnixListener::bind(path).map_err(|e| format!("{}: {}", path, e))?;
+    let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+    Ok(Connection::Unix(stream))
+}
+
+#[cfg(not(unix))]
+fn accept_unix(path: &str) -> Result<Connection, String> {
+    Err(format!("unix:{} needs Unix sockets, use a loopback address such as 127.0.0.1:7878", path))
+}
+
+fn state_message(network: &Network, outcome: Option<Outcome>) -> String {
+    let mut vehicles = vec![];
+    for (index, intersection) in network.intersections.iter().enumerate() {
+        for car in &intersection.vehicles {
+            let centre = car.get_centre();
+            vehicles.push(
+                format!(
+                    "{{\"id\":{},\"intersection\":{},\"origin\":{},\"direction\":{},\"lane\":{},\
+                     \"kind\":{},\"x\":{},\"y\":{},\"velocity\":{},\"max_speed\":{},\
+                     \"distance_to_stop_line\":{},\"delay\":{},\"in_box\":{},\"held\":{}}}",
+                    car.id,
+                    index,
+                    quote(car.origin.name()),
+                    quote(car.direction.name()),
+                    car.lane,
+                    quote(car.kind.name()),
+                    centre.x,
+                    centre.y,
+                    car.velocity,
+                    car.kind.max_speed(),
+                    car.get_distance_to_stop_line(),
+                    car.get_delay() / 1000.0,
+                    car.is_in_intersection(),
+                    car.held
+                )
+            );
+        }
+    }
+    let episode = match outcome {
+        Some(outcome) => format!(",\"reward\":{},\"done\":{}", outcome.reward, outcome.done),
+        None => String::new(),
+    };
+    format!(
+        "{{\"type\":\"state\",\"tick\":{}{},\"vehicles\":[{}]}}",
+        network.tick(),
+        episode,
+        vehicles.join(",")
+    )
+}
+
+fn error_message(message: &str) -> String {
+    format!("{{\"type\":\"error\",\"message\":{}}}", quote(message))
+}
+
+fn member_number(json: &Json, key: &str) -> Result<f64, String> {
+    json.get(key)
+        .and_then(Json::as_f64)
+        .ok_or(format!("'{}' must be a number", key))
+}
+
+// Ids, indices and seeds must be whole numbers in range, as casting would quietly round, clamp
+// or wrap anything else onto a different vehicle or lane
+fn member_whole_number(json: &Json, key: &str, max: u64) -> Result<u64, String> {
+    let number = member_number(json, key)?;
+    if number < 0.0 || number.fract() != 0.0 || number > (max as f64) {
+        return Err(format!("'{}' must be a whole number from 0 to {}", key, max));
+    }
+    Ok(number as u64)
+}
+
+// {"reset":7}, or a command such as
+// {"speeds":[{"id":3,"speed":1.5}],"grants":[{"intersection":0,"origin":"north","lane":1}]},
+// where both members may be left out and grants may be null to release every lane
+fn parse_reply(line: &str) -> Result<Reply, String> {
+    let json = Json::parse(line)?;
+    if !matches!(json, Json::Object(_)) {
+        return Err("a reply must be a JSON object".to_string());
+    }
+    if json.get("reset").is_some() {
+        // Whole numbers above 2^53 cannot all be told apart as JSON numbers
+        let seed = member_whole_number(&json, "reset", 1 << 53)?;
+        return Ok(Reply::Reset(seed));
+    }
+    let mut command = Command::new();
+    if let Some(speeds) = json.get("speeds") {
+        for speed in speeds.as_array().ok_or("'speeds' must be an array")? {
+            let id = member_whole_number(speed, "id", u32::MAX as u64)?;
+            let target = member_number(speed, "speed")?;
+            command.target_speeds.push((id as u32, target as f32));
+        }
+    }
+    match json.get("grants") {
+        None | Some(Json::Null) => {}
+        Some(grants) => {
+            let mut lane_grants = vec![];
+            for grant in grants.as_array().ok_or("'grants' must be an array or null")? {
+                let origin = grant
+                    .get("origin")
+                    .and_then(Json::as_str)
+                    .and_then(Direction::from_name)
+                    .ok_or("'origin' must be north, south, east or west")?;
+                let intersection = member_whole_number(grant, "intersection", u32::MAX as u64)?;
+                let lane = member_whole_number(grant, "lane", u32::MAX as u64)?;
+                lane_grants.push(LaneGrant {
+                    intersection: intersection as usize,
+                    origin,
+                    lane: lane as usize,
+                });
+            }
+            command.lane_grants = Some(lane_grants);
+        }
+    }
+    Ok(Reply::Command(command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(line: &str) -> Command {
+        match parse_reply(line) {
+            Ok(Reply::Command(command)) => command,
+            other => panic!("{:?} gave {:?}", line, other),
+        }
+    }
+
+    #[test]
+    fn parses_commands() {
+        let parsed = command(
+            r#"{"speeds":[{"id":3,"speed":1.5}],
+                "grants":[{"intersection":0,"origin":"north","lane":1}]}"#
+        );
+        assert_eq!(parsed.target_speeds, vec![(3, 1.5)]);
+        assert_eq!(
+            parsed.lane_grants,
+            Some(vec![LaneGrant { intersection: 0, origin: Direction::North, lane: 1 }])
+        );
+        let largest = command(r#"{"speeds":[{"id":4294967295,"speed":2}]}"#);
+        assert_eq!(largest.target_speeds, vec![(u32::MAX, 2.0)]);
+    }
+
+    #[test]
+    fn missing_or_null_grants_release_every_lane() {
+        for line in ["{}", r#"{"speeds":[]}"#, r#"{"grants":null}"#] {
+            let parsed = command(line);
+            assert!(parsed.target_speeds.is_empty());
+            assert_eq!(parsed.lane_grants, None, "{:?}", line);
+        }
+        assert_eq!(command(r#"{"grants":[]}"#).lane_grants, Some(vec![]));
+    }
+
+    #[test]
+    fn parses_resets() {
+        assert!(matches!(parse_reply(r#"{"reset":7}"#), Ok(Reply::Reset(7))));
+        assert!(matches!(parse_reply(r#"{"reset":0}"#), Ok(Reply::Reset(0))));
+        for line in [
+            r#"{"reset":-1}"#,
+            r#"{"reset":1.5}"#,
+            r#"{"reset":null}"#,
+            r#"{"reset":1e300}"#,
+        ] {
+            assert!(parse_reply(line).is_err(), "{:?} was accepted", line);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        for line in [
+            "",
+            "{",
+            "[]",
+            "null",
+            r#"{"speeds":null}"#,
+            r#"{"speeds":{}}"#,
+            r#"{"speeds":[{"id":3}]}"#,
+            r#"{"speeds":[{"id":"3","speed":1}]}"#,
+            r#"{"grants":{}}"#,
+            r#"{"grants":[null]}"#,
+            r#"{"grants":[{"intersection":0,"lane":1}]}"#,
+            r#"{"grants":[{"intersection":0,"origin":"up","lane":1}]}"#,
+            r#"{"grants":[{"origin":"north","lane":1}]}"#,
+            r#"{"speeds":[{"id":-1,"speed":1}]}"#,
+            r#"{"speeds":[{"id":4.7,"speed":1}]}"#,
+            r#"{"speeds":[{"id":4294967296,"speed":1}]}"#,
+            r#"{"speeds":[{"id":1e300,"speed":1}]}"#,
+            r#"{"grants":[{"intersection":0,"origin":"north","lane":-1}]}"#,
+            r#"{"grants":[{"intersection":0,"origin":"north","lane":0.5}]}"#,
+            r#"{"grants":[{"intersection":-0.5,"origin":"north","lane":0}]}"#,
+            r#"{"grants":[{"intersection":1e20,"origin":"north","lane":0}]}"#,
+        ] {
+            assert!(parse_reply(line).is_err(), "{:?} was accepted", line);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use crate::{
+    network::{ Network, NetworkLayout },
+    intersection::Direction,
+    vehicle::{ Vehicle, VehicleKind, VehicleMix },
+    layout::LaneLayout,
+    pedestrian::PedestrianDemand,
+    algorithm::Manager,
+    config::Config,
+};
+
+// Numbers describing one vehicle in an observation:
+// id, intersection, origin, exit, lane, kind, centre x, centre y, velocity,
+// distance to the stop line, delay in seconds and 1 once it is in the box.
+// Directions count North, South, East, West from 0 and kinds follow VehicleKind::ALL.
+pub const STATE_SIZE: usize = 12;
+pub type VehicleState = [f32; STATE_SIZE];
+
+// Reward taken away for each crash and close call, where a second of delay costs 1
+const CRASH_PENALTY: f32 = 100.0;
+const CLOSE_CALL_PENALTY: f32 = 10.0;
+
+// An approach lane whose vehicles may pass the stop line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LaneGrant {
+    pub intersection: usize,
+    pub origin: Direction,
+    pub lane: usize,
+}
+
+// A controller's action for one step. Listed vehicles go no faster than their target speed,
+// and while there are lane grants only vehicles in a granted lane may pass the stop line.
+// Vehicles follow commands under the external manager only.
+#[derive(Debug, Clone)]
+pub struct Command {
+    pub target_speeds: Vec<(u32, f32)>,
+    pub lane_grants: Option<Vec<LaneGrant>>,
+}
+
+impl Command {
+    pub fn new() -> Self {
+        Self {
+            target_speeds: vec![],
+            lane_grants: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Observation {
+    pub tick: u64,
+    pub vehicles: Vec<VehicleState>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub observation: Observation,
+    pub reward: f32,
+    pub done: bool,
+}
+
+// Delay in milliseconds, crashes and close calls up to now, finished vehicles included
+#[derive(Debug, Clone, Copy, Default)]
+struct Totals {
+    delay: f32,
+    crashes: u32,
+    close_calls: u32,
+}
+
+// The simulation as a training environment: no window, a fixed spawn rate and every random
+// draw taken from the seed given to reset, so an episode can be played again exactly
+pub struct Environment {
+    pub network: Network,
+    pub episode_ticks: u64,
+    spawn_interval_ticks: u64,
+    network_layout: NetworkLayout,
+    lanes: LaneLayout,
+    vehicle_mix: VehicleMix,
+    pedestrian_demand: PedestrianDemand,
+    manager: Manager,
+    max_wait: Option<f32>,
+    next_vehicle_id: u32,
+    last_spawn_tick: u64,
+    totals: Totals,
+}
+
+impl Environment {
+    pub fn new(config: &Config) -> Self {
+        let mut environment = Self {
+            network: Network::new(&config.network),
+            episode_ticks: config.episode_ticks,
+            spawn_interval_ticks: config.spawn_interval_ticks,
+            network_layout: config.network.clone(),
+            lanes: config.lanes.clone(),
+            vehicle_mix: config.vehicle_mix.clone(),
+            pedestrian_demand: config.pedestrian_demand.clone(),
+            manager: config.manager,
+            max_wait: config.max_wait,
+            next_vehicle_id: 0,
+            last_spawn_tick: 0,
+            totals: Totals::default(),
+        };
+        environment.reset(config.seed.unwrap_or(0));
+        environment
+    }
+
+    // Starts a new episode on an empty network
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        let mut network = Network::new(&self.network_layout);
+        for intersection in &mut network.intersections {
+            intersection.vehicle_mix = self.vehicle_mix.clone();
+            intersection.pedestrian_demand = self.pedestrian_demand.clone();
+            intersection.manager = self.manager;
+            intersection.max_wait = self.max_wait;
+        }
+        network.set_layout(&self.lanes);
+        network.seed(seed);
+        self.network = network;
+        self.next_vehicle_id = 0;
+        self.last_spawn_tick = 0;
+        self.totals = Totals::default();
+        self.observe()
+    }
+
+    pub fn observe(&self) -> Observation {
+        let vehicles = self.network.intersections
+            .iter()
+            .enumerate()
+            .flat_map(|(index, intersection)| {
+                intersection.vehicles.iter().map(move |car| vehicle_state(index, car))
+            })
+            .collect();
+        Observation { tick: self.network.tick(), vehicles }
+    }
+
+    // Applies the command, runs one tick and rewards it by the delay, crashes and close calls
+    // it added
+    pub fn step(&mut self, command: &Command) -> Step {
+        apply_command(&mut self.network, command);
+        if self.network.tick() - self.last_spawn_tick >= self.spawn_interval_ticks {
+            self.network.add_random_vehicle(self.next_vehicle_id);
+            self.next_vehicle_id += 1;
+            self.last_spawn_tick = self.network.tick();
+        }
+        self.network.update();
+
+        let totals = self.totals();
+        let reward =
+            -(totals.delay - self.totals.delay) / 1000.0 -
+            CRASH_PENALTY * ((totals.crashes - self.totals.crashes) as f32) -
+            CLOSE_CALL_PENALTY * ((totals.close_calls - self.totals.close_calls) as f32);
+        self.totals = totals;
+
+        Step {
+            observation: self.observe(),
+            reward,
+            done: self.network.tick() >= self.episode_ticks,
+        }
+    }
+
+    fn totals(&self) -> Totals {
+        let mut totals = Totals::default();
+        for intersection in &self.network.intersections {
+            totals.delay += intersection.stats.movement_delays
+                .iter()
+                .map(|(_, _, delay)| delay)
+                .sum::<f32>();
+            // Halved, as both vehicles involved record each one
+            totals.crashes += intersection.live_crashes();
+            totals.close_calls += intersection.live_close_calls();
+            for car in &intersection.vehicles {
+                totals.delay += car.get_delay();
+            }
+        }
+        totals
+    }
+}
+
+// A command lasts until the next one, vehicles left out of it are free again
+pub fn apply_command(network: &mut Network, command: &Command) {
+    for (index, intersection) in network.intersections.iter_mut().enumerate() {
+        for car in &mut intersection.vehicles {
+            car.target_speed = command.target_speeds
+                .iter()
+                .find(|(id, _)| *id == car.id)
+                .map(|&(_, speed)| speed.max(0.0));
+            car.held = command.lane_grants.as_ref().is_some_and(|grants| {
+                !grants.contains(
+                    &(LaneGrant { intersection: index, origin: car.origin, lane: car.lane })
+                )
+            });
+        }
+    }
+}
+
+fn vehicle_state(intersection: usize, car: &Vehicle) -> VehicleState {
+    let centre = car.get_centre();
+    let kind = VehicleKind::ALL.iter()
+        .position(|&k| k == car.kind)
+        .unwrap_or(0);
+    [
+        car.id as f32,
+        intersection as f32,
+        car.origin as usize as f32,
+        car.direction as usize as f32,
+        car.lane as f32,
+        kind as f32,
+        centre.x as f32,
+        centre.y as f32,
+        car.velocity,
+        car.get_distance_to_stop_line() as f32,
+        car.get_delay() / 1000.0,
+        if car.is_in_intersection() { 1.0 } else { 0.0 },
+    ]
+}
+
+// Vehicles drive up to a held lane's stop line and wait there, and never go faster than the
+// target speed they were sent
+pub fn command_velocity_limit(car: &Vehicle) -> f32 {
+    let distance = car.get_distance_to_stop_line();
+    let hold = if car.held && distance >= 0 { distance as f32 } else { f32::MAX };
+    hold.min(car.target_speed.unwrap_or(f32::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Point;
+
+    // An environment with no vehicles of its own, two cars put in its box and the penalty for
+    // the step after, with the delay it added taken back out
+    fn staged_penalty(gap: i32) -> (Totals, f32) {
+        let mut config = Config::new();
+        config.spawn_interval_ticks = u64::MAX;
+        let mut environment = Environment::new(&config);
+        let intersection = &mut environment.network.intersections[0];
+        let layout = intersection.layout.clone();
+        let car = |origin: Direction, id| {
+            Vehicle::new_in_lane(origin, origin.opposite(), VehicleKind::Car, &layout, 1, id)
+        };
+        let (mut first, mut second) = (car(Direction::North, 0), car(Direction::East, 1));
+        first.position = Point::new(280, 280);
+        second.position = Point::new(first.get_rect().right() + gap, 280);
+        intersection.vehicles = vec![first, second];
+
+        let before = environment.totals();
+        let step = environment.step(&Command::new());
+        let after = environment.totals();
+        let delay = (after.delay - before.delay) / 1000.0;
+        let counted = Totals {
+            delay: 0.0,
+            crashes: after.crashes - before.crashes,
+            close_calls: after.close_calls - before.close_calls,
+        };
+        (counted, -(step.reward + delay))
+    }
+
+    #[test]
+    fn close_call_costs_its_penalty_once() {
+        let (counted, penalty) = staged_penalty(2);
+        assert_eq!((counted.crashes, counted.close_calls), (0, 1));
+        assert!((penalty - CLOSE_CALL_PENALTY).abs() < 1e-3, "penalty {}", penalty);
+    }
+
+    #[test]
+    fn crash_costs_its_penalty_once() {
+        // Overlapping cars also pass within the safety gap, so a crash is a close call as well
+        let (counted, penalty) = staged_penalty(-10);
+        assert_eq!((counted.crashes, counted.close_calls), (1, 1));
+        assert!((penalty - CLOSE_CALL_PENALTY - 100.0).abs() < 1e-3, "penalty {}", penalty);
+    }
+}
@@ -0,0 +1,136 @@
+// Points and rectangles in window pixels for the simulation, which has to run without SDL.
+// They behave like SDL's: a rectangle is at least one pixel each way, its right and bottom
+// edges are just outside it, and rectangles that only share an edge do not intersect.
+
+use std::ops::AddAssign;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Point {
+    pub fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+}
+
+impl AddAssign for Point {
+    fn add_assign(&mut self, other: Point) {
+        self.x += other.x;
+        self.y += other.y;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rect {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Self { x, y, width: width.max(1), height: height.max(1) }
+    }
+
+    // Centred with SDL's rounding, so an odd size leaves the extra pixel right and below
+    pub fn from_center(center: Point, width: u32, height: u32) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        Self::new(center.x - (width as i32) / 2, center.y - (height as i32) / 2, width, height)
+    }
+
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn left(&self) -> i32 {
+        self.x
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x + (self.width as i32)
+    }
+
+    pub fn top(&self) -> i32 {
+        self.y
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + (self.height as i32)
+    }
+
+    pub fn center(&self) -> Point {
+        Point::new(self.x + (self.width as i32) / 2, self.y + (self.height as i32) / 2)
+    }
+
+    pub fn set_x(&mut self, x: i32) {
+        self.x = x;
+    }
+
+    pub fn set_y(&mut self, y: i32) {
+        self.y = y;
+    }
+
+    pub fn contains_point(&self, point: Point) -> bool {
+        point.x >= self.left() &&
+            point.x < self.right() &&
+            point.y >= self.top() &&
+            point.y < self.bottom()
+    }
+
+    pub fn contains_rect(&self, other: Rect) -> bool {
+        other.left() >= self.left() &&
+            other.right() <= self.right() &&
+            other.top() >= self.top() &&
+            other.bottom() <= self.bottom()
+    }
+
+    pub fn has_intersection(&self, other: Rect) -> bool {
+        self.left().max(other.left()) < self.right().min(other.right()) &&
+            self.top().max(other.top()) < self.bottom().min(other.bottom())
+    }
+
+    // The smallest rectangle covering both
+    pub fn union(&self, other: Rect) -> Rect {
+        let (left, top) = (self.left().min(other.left()), self.top().min(other.top()));
+        let (right, bottom) = (self.right().max(other.right()), self.bottom().max(other.bottom()));
+        Rect::new(left, top, (right - left) as u32, (bottom - top) as u32)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<Point> for sdl2::rect::Point {
+    fn from(point: Point) -> Self {
+        sdl2::rect::Point::new(point.x, point.y)
+    }
+}
+
+#[cfg(feature = "gui")]
+impl From<Rect> for sdl2::rect::Rect {
+    fn from(rect: Rect) -> Self {
+        sdl2::rect::Rect::new(rect.x, rect.y, rect.width, rect.height)
+    }
+}
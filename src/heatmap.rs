@@ -0,0 +1,133 @@
+use image::{ Rgba, RgbaImage };
+
+use crate::{ geometry::Point, WINDOW_WIDTH, WINDOW_HEIGHT };
+
+pub const HEATMAP_CELL_SIZE: u32 = 10;
+
+// Cars held back by the algorithm crawl at its minimum velocity, treat that as stopped
+const STOPPED_VELOCITY: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeatmapMetric {
+    Delay,
+    CloseCalls,
+    Crashes,
+}
+
+impl HeatmapMetric {
+    pub fn next(metric: Option<HeatmapMetric>) -> Option<HeatmapMetric> {
+        match metric {
+            None => Some(HeatmapMetric::Delay),
+            Some(HeatmapMetric::Delay) => Some(HeatmapMetric::CloseCalls),
+            Some(HeatmapMetric::CloseCalls) => Some(HeatmapMetric::Crashes),
+            Some(HeatmapMetric::Crashes) => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HeatmapMetric::Delay => "delay",
+            HeatmapMetric::CloseCalls => "close_calls",
+            HeatmapMetric::Crashes => "crashes",
+        }
+    }
+}
+
+pub struct Heatmap {
+    pub columns: u32,
+    pub rows: u32,
+    pub stopped_time: Vec<f32>,
+    pub close_calls: Vec<u32>,
+    pub crashes: Vec<u32>,
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        let columns = WINDOW_WIDTH / HEATMAP_CELL_SIZE;
+        let rows = WINDOW_HEIGHT / HEATMAP_CELL_SIZE;
+        let cells = (columns * rows) as usize;
+        Self {
+            columns,
+            rows,
+            stopped_time: vec![0.0; cells],
+            close_calls: vec![0; cells],
+            crashes: vec![0; cells],
+        }
+    }
+
+    fn cell_index(&self, point: Point) -> Option<usize> {
+        if point.x < 0 || point.y < 0 {
+            return None;
+        }
+        let column = (point.x as u32) / HEATMAP_CELL_SIZE;
+        let row = (point.y as u32) / HEATMAP_CELL_SIZE;
+        if column >= self.columns || row >= self.rows {
+            return None;
+        }
+        Some((row * self.columns + column) as usize)
+    }
+
+    pub fn add_velocity_sample(&mut self, point: Point, velocity: f32, delta_time: f32) {
+        if velocity <= STOPPED_VELOCITY {
+            if let Some(index) = self.cell_index(point) {
+                self.stopped_time[index] += delta_time;
+            }
+        }
+    }
+
+    pub fn add_close_call(&mut self, point: Point) {
+        if let Some(index) = self.cell_index(point) {
+            self.close_calls[index] += 1;
+        }
+    }
+
+    pub fn add_crash(&mut self, point: Point) {
+        if let Some(index) = self.cell_index(point) {
+            self.crashes[index] += 1;
+        }
+    }
+
+    // Cell values scaled to 0..1 against the busiest cell
+    pub fn normalised(&self, metric: HeatmapMetric) -> Vec<f32> {
+        let values: Vec<f32> = match metric {
+            HeatmapMetric::Delay => self.stopped_time.clone(),
+            HeatmapMetric::CloseCalls => self.close_calls.iter().map(|&v| v as f32).collect(),
+            HeatmapMetric::Crashes => self.crashes.iter().map(|&v| v as f32).collect(),
+        };
+        let max = values.iter().cloned().fold(0.0, f32::max);
+        if max <= 0.0 {
+            return vec![0.0; values.len()];
+        }
+        values
+            .iter()
+            .map(|v| v / max)
+            .collect()
+    }
+
+    pub fn export_png(&self, metric: HeatmapMetric, path: &str) -> Result<(), String> {
+        let values = self.normalised(metric);
+        let image = RgbaImage::from_fn(WINDOW_WIDTH, WINDOW_HEIGHT, |x, y| {
+            let column = (x / HEATMAP_CELL_SIZE).min(self.columns - 1);
+            let row = (y / HEATMAP_CELL_SIZE).min(self.rows - 1);
+            let (r, g, b, a) = heat_colour(values[(row * self.columns + column) as usize]);
+            Rgba([r, g, b, a])
+        });
+        image.save(path).map_err(|e| e.to_string())
+    }
+}
+
+// Blue through yellow to red, transparent where nothing was recorded
+pub fn heat_colour(value: f32) -> (u8, u8, u8, u8) {
+    if value <= 0.0 {
+        return (0, 0, 0, 0);
+    }
+    let value = value.min(1.0);
+    let (r, g, b) = if value < 0.5 {
+        let t = value * 2.0;
+        ((255.0 * t) as u8, (255.0 * t) as u8, (255.0 * (1.0 - t)) as u8)
+    } else {
+        let t = (value - 0.5) * 2.0;
+        (255, (255.0 * (1.0 - t)) as u8, 0)
+    };
+    (r, g, b, (80.0 + 140.0 * value) as u8)
+}
@@ -2,14 +2,29 @@
 
 use std::collections::VecDeque;
 
-use rand::Rng;
-use sdl2::keyboard::Keycode;
+use rand::{ rngs::StdRng, Rng, SeedableRng };
 
 use crate::{
-    vehicle::{ Vehicle },
-    algorithm::determine_velocity,
+    geometry::{ Point, Rect },
+    vehicle::{ Vehicle, VehicleKind, VehicleMix, get_lane_position },
+    layout::{ DrivingSide, Junction, LaneLayout, LaneUse, LANE_WIDTH, ROUNDABOUT_LANE },
+    algorithm::{ determine_velocity, Manager, ARRIVAL_DISTANCE },
+    conflict::ConflictTable,
+    platoon::form_platoons,
+    schedule::{ plan_entries, REPLAN_INTERVAL },
+    auction::{ run_auction, draw_value_of_time },
     statistics::Statistics,
-    physics::{ get_close_calls_for_vehicle, will_vehicles_collide },
+    heatmap::Heatmap,
+    pedestrian::{
+        Crossing,
+        Pedestrian,
+        PedestrianDemand,
+        update_pedestrians,
+        crossing_velocity_limit,
+    },
+    WINDOW_WIDTH,
+    WINDOW_HEIGHT,
+    physics::{ get_close_calls_for_vehicle, get_crashes_for_vehicle, will_vehicles_collide },
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -20,50 +35,183 @@ pub enum Direction {
     West,
 }
 
+impl Direction {
+    pub fn from_name(name: &str) -> Option<Direction> {
+        match name {
+            "north" => Some(Direction::North),
+            "south" => Some(Direction::South),
+            "east" => Some(Direction::East),
+            "west" => Some(Direction::West),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::North => "north",
+            Direction::South => "south",
+            Direction::East => "east",
+            Direction::West => "west",
+        }
+    }
+
+    pub fn opposite(&self) -> Direction {
+        match self {
+            Direction::North => Direction::South,
+            Direction::South => Direction::North,
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
+        }
+    }
+
+    // Exit reached by turning right when arriving from this side
+    pub fn right_turn(&self) -> Direction {
+        match self {
+            Direction::North => Direction::West,
+            Direction::South => Direction::East,
+            Direction::East => Direction::North,
+            Direction::West => Direction::South,
+        }
+    }
+}
+
 pub struct Intersection {
     // Define any necessary fields for the intersection
     // For example, a collection of vehicles currently in the intersection
-    pub lanes: [Lane; 12],
+    pub layout: LaneLayout,
+    pub lanes: Vec<Lane>,
+    // Which lane movements can meet, rebuilt whenever the layout changes
+    pub conflicts: ConflictTable,
+    pub manager: Manager,
+    // Delay in milliseconds after which a car is served before cars that have waited less
+    pub max_wait: Option<f32>,
+    // Total delay in ticks of the latest optimised plan or auction
+    pub planned_delay: f32,
     pub queued_vehicles: VecDeque<Vehicle>,
     pub vehicles: Vec<Vehicle>,
     pub stats: Statistics,
+    pub tick: u64,
+    pub heatmap: Heatmap,
+    pub vehicle_mix: VehicleMix,
+    pub crossings: [Crossing; 4],
+    pub pedestrians: Vec<Pedestrian>,
+    pub pedestrian_demand: PedestrianDemand,
+    // Exits whose downstream link is full, vehicles wait at the edge instead of leaving
+    pub blocked_exits: Vec<Direction>,
+    // Vehicles that left during the last update, for a network to pass on
+    pub departed: Vec<Vehicle>,
+    // Every random draw comes from here, so a seeded run plays out the same each time
+    pub rng: StdRng,
 }
 
 impl Intersection {
     pub fn new() -> Self {
-        let lanes = [
-            Lane::new(Direction::North, Direction::South),
-            Lane::new(Direction::North, Direction::East),
-            Lane::new(Direction::North, Direction::West),
-            Lane::new(Direction::South, Direction::North),
-            Lane::new(Direction::South, Direction::East),
-            Lane::new(Direction::South, Direction::West),
-            Lane::new(Direction::East, Direction::North),
-            Lane::new(Direction::East, Direction::South),
-            Lane::new(Direction::East, Direction::West),
-            Lane::new(Direction::West, Direction::North),
-            Lane::new(Direction::West, Direction::South),
-            Lane::new(Direction::West, Direction::East),
-        ];
+        let layout = LaneLayout::new();
         let stats = Statistics::new();
         Self {
-            lanes,
+            lanes: build_lanes(&layout),
+            conflicts: ConflictTable::new(&layout),
+            manager: Manager::DistanceToFinish,
+            max_wait: None,
+            planned_delay: 0.0,
+            layout,
             queued_vehicles: VecDeque::new(),
             vehicles: vec![],
             stats,
+            tick: 0,
+            heatmap: Heatmap::new(),
+            vehicle_mix: VehicleMix::new(),
+            crossings: [
+                Crossing::new(Direction::North),
+                Crossing::new(Direction::South),
+                Crossing::new(Direction::East),
+                Crossing::new(Direction::West),
+            ],
+            pedestrians: vec![],
+            pedestrian_demand: PedestrianDemand::new(),
+            blocked_exits: vec![],
+            departed: vec![],
+            rng: StdRng::from_entropy(),
         }
     }
 
+    pub fn set_layout(&mut self, layout: LaneLayout) {
+        self.lanes = build_lanes(&layout);
+        self.conflicts = ConflictTable::new(&layout);
+        self.layout = layout;
+    }
+
     pub fn update(&mut self) {
+        self.tick += 1;
+        self.departed.clear();
+        self.update_pedestrians();
+
+        if self.manager == Manager::Platoon {
+            form_platoons(&mut self.vehicles);
+        }
+        if self.layout.junction == Junction::Cross && self.tick.is_multiple_of(REPLAN_INTERVAL) {
+            match self.manager {
+                Manager::Optimised => {
+                    self.planned_delay = plan_entries(
+                        &mut self.vehicles,
+                        &self.conflicts,
+                        self.max_wait,
+                        self.tick
+                    );
+                }
+                Manager::Auction => {
+                    self.planned_delay = run_auction(
+                        &mut self.vehicles,
+                        &self.conflicts,
+                        self.max_wait,
+                        self.tick
+                    );
+                }
+                _ => {}
+            }
+        }
         let nc = self.vehicles.clone();
         let mut close_call_count = 0;
 
         for (_, car) in self.vehicles.iter_mut().enumerate() {
             let all_cars: Vec<Vehicle> = nc.clone();
 
+            let close_calls_before = car.close_calls.len();
+            let crashes_before = car.crashes.len();
             get_close_calls_for_vehicle(car, &all_cars);
+            get_crashes_for_vehicle(car, &all_cars);
+            for _ in close_calls_before..car.close_calls.len() {
+                self.heatmap.add_close_call(car.get_centre());
+            }
+            for _ in crashes_before..car.crashes.len() {
+                self.heatmap.add_crash(car.get_centre());
+            }
 
-            let new_velocity = determine_velocity(car, all_cars);
+            if car.arrival.is_none() && car.get_distance_to_stop_line() <= ARRIVAL_DISTANCE {
+                car.arrival = Some(self.tick);
+            }
+            if
+                self.manager == Manager::Auction &&
+                !car.paid &&
+                car.get_distance_to_stop_line() <= 0
+            {
+                car.paid = true;
+                car.budget -= car.price;
+                self.stats.add_auction_payment(car.price);
+            }
+            let previous_velocity = car.velocity;
+            let new_velocity = determine_velocity(
+                car,
+                all_cars,
+                &self.conflicts,
+                self.manager,
+                self.max_wait,
+                self.tick
+            );
+            let new_velocity = car.limit_acceleration(previous_velocity, new_velocity);
+            car.set_velocity(new_velocity);
+            let new_velocity = crossing_velocity_limit(car, &self.crossings, &self.pedestrians);
+            let new_velocity = new_velocity.min(exit_velocity_limit(car, &self.blocked_exits));
 
             if new_velocity > self.stats.max_velocity {
                 self.stats.set_max_velocity(new_velocity);
@@ -74,6 +222,7 @@ impl Intersection {
 
             car.set_velocity(new_velocity);
             car.update(1.0 / 0.06);
+            self.heatmap.add_velocity_sample(car.get_centre(), car.velocity, 1.0 / 0.06);
 
             if !car.is_in_entire_intersection() {
                 if car.time > self.stats.max_time {
@@ -94,71 +243,139 @@ impl Intersection {
         for veh in &self.vehicles {
             if !veh.is_in_entire_intersection() {
                 self.stats.add_close_call(veh.close_calls.len() as u32);
+                self.stats.add_crash(veh.crashes.len() as u32);
+                if veh.kind == VehicleKind::Emergency {
+                    self.stats.add_emergency_delay(veh.get_delay());
+                }
+                self.stats.add_delay_cost(veh.value_of_time * (veh.get_delay() / 1000.0));
+                self.stats.add_vehicle_delay(veh.movement(), veh.get_delay());
             }
         }
 
         //remove vehicles from intersection if out of bounds
-        self.vehicles.retain(|v| v.is_in_entire_intersection());
+        let (remaining, departed): (Vec<Vehicle>, Vec<Vehicle>) = self.vehicles
+            .drain(..)
+            .partition(|v| v.is_in_entire_intersection());
+        self.vehicles = remaining;
+        self.departed = departed;
 
         let cars_after = self.vehicles.len().clone();
 
         self.stats.add_to_total_vehicles((cars_before as u32) - (cars_after as u32));
     }
 
-    pub fn add_directed_vehicle(&mut self, keycode: Keycode, id: u32) {
-        let mut origin = Direction::South;
-        let mut directions = vec![
-            Direction::North,
-            Direction::South,
-            Direction::East,
-            Direction::West
-        ];
-
-        match keycode {
-            Keycode::Up => {
-                origin = Direction::South;
-            }
-            Keycode::Down => {
-                origin = Direction::North;
-            }
-            Keycode::Left => {
-                origin = Direction::East;
+    fn update_pedestrians(&mut self) {
+        for crossing in self.crossings.iter().filter(|c| self.layout.has_arm(c.arm)) {
+            if self.pedestrian_demand.arrives(&mut self.rng) {
+                self.pedestrians.push(Pedestrian::new(crossing, self.rng.gen::<bool>()));
             }
-            Keycode::Right => {
-                origin = Direction::West;
-            }
-            _ => (),
         }
 
-        directions.retain(|&d| d != origin);
-        let random_index = rand::thread_rng().gen_range(0..directions.len());
-        let direction = directions[random_index];
-
-        self.add_vehicle_to_queue(origin, direction, id);
+        let finished = update_pedestrians(
+            &mut self.pedestrians,
+            &self.crossings,
+            &self.vehicles,
+            1.0 / 0.06
+        );
+        for wait_time in finished {
+            self.stats.add_pedestrian_wait(wait_time);
+        }
     }
 
-    pub fn add_random_vehicle(&mut self, id: u32) {
-        //get random direction and set origin
-        let mut directions = vec![
-            Direction::North,
-            Direction::South,
-            Direction::East,
-            Direction::West
-        ];
-        let mut random_index = rand::thread_rng().gen_range(0..directions.len());
-        let origin = directions[random_index];
+    pub fn add_emergency_vehicle(&mut self, id: u32) {
+        let arms = &self.layout.arms;
+        let origin = arms[self.rng.gen_range(0..arms.len())];
+        let exits = self.layout.exits(origin);
+        let direction = exits[self.rng.gen_range(0..exits.len())];
+        self.queued_vehicles.push_back(
+            Vehicle::new(origin, direction, VehicleKind::Emergency, &self.layout, id, &mut self.rng)
+        );
+    }
 
-        //remove origin direction from choosable directions and set output direction
-        directions.retain(|&d| d != origin);
-        random_index = rand::thread_rng().gen_range(0..directions.len());
-        let direction = directions[random_index];
+    // Queues a vehicle that takes the first exit of its route here and the rest further on
+    pub fn add_routed_vehicle(
+        &mut self,
+        origin: Direction,
+        kind: VehicleKind,
+        mut route: VecDeque<Direction>,
+        id: u32
+    ) {
+        let direction = route.pop_front().expect("route without an exit");
+        let mut vehicle = Vehicle::new(origin, direction, kind, &self.layout, id, &mut self.rng);
+        vehicle.route = route;
+        self.queued_vehicles.push_back(vehicle);
+    }
 
-        self.add_vehicle_to_queue(direction, origin, id)
+    // A vehicle arriving over a link from a neighbouring intersection takes the next exit of
+    // its route, or picks one at random once the route has run out
+    pub fn add_continuing_vehicle(&mut self, vehicle: &Vehicle) {
+        let origin = vehicle.direction.opposite();
+        let mut route = vehicle.route.clone();
+        let direction = match route.pop_front() {
+            Some(direction) => direction,
+            None => {
+                let directions: Vec<Direction> = self.layout
+                    .exits(origin)
+                    .into_iter()
+                    .filter(|&d| vehicle.kind.can_make(origin, d, self.layout.driving_side))
+                    .collect();
+                directions[self.rng.gen_range(0..directions.len())]
+            }
+        };
+        let mut continuing = Vehicle::new(
+            origin,
+            direction,
+            vehicle.kind,
+            &self.layout,
+            vehicle.id,
+            &mut self.rng
+        );
+        continuing.route = route;
+        // Still the same driver, with the same value of time and what is left of their budget
+        continuing.value_of_time = vehicle.value_of_time;
+        continuing.budget = vehicle.budget;
+        self.queued_vehicles.push_back(continuing);
     }
 
-    fn add_vehicle_to_queue(&mut self, origin: Direction, direction: Direction, id: u32) {
-        let vehicle = Vehicle::new(origin, direction, id);
+    pub fn add_vehicle_at(&mut self, point: Point, id: u32) -> bool {
+        let lane = match self.lanes.iter().find(|l| l.approach_rect().contains_point(point)) {
+            Some(lane) => lane,
+            None => {
+                return false;
+            }
+        };
+        // Any movement the clicked lane allows, kept in that lane
+        let directions: Vec<Direction> = self.layout
+            .exits(lane.origin)
+            .into_iter()
+            .filter(|&d| lane.lane_use.allows(lane.origin, d))
+            .collect();
+        // On a T-junction a lane may only lead to the missing arm
+        if directions.is_empty() {
+            return false;
+        }
+        let direction = directions[self.rng.gen_range(0..directions.len())];
+        let driving_side = self.layout.driving_side;
+        let kind = self.vehicle_mix.pick(&mut self.rng, |k| {
+            k.can_make(lane.origin, direction, driving_side)
+        });
+        let mut vehicle = Vehicle::new_in_lane(
+            lane.origin,
+            direction,
+            kind,
+            &self.layout,
+            lane.index,
+            id
+        );
+        vehicle.value_of_time = draw_value_of_time(&mut self.rng);
         self.queued_vehicles.push_back(vehicle);
+        true
+    }
+
+    pub fn vehicle_at(&self, point: Point) -> Option<&Vehicle> {
+        self.vehicles
+            .iter()
+            .find(|v| v.get_rect().contains_point(point))
     }
 
     pub fn add_vehicle(&mut self) {
@@ -179,6 +396,42 @@ impl Intersection {
         }
     }
 
+    pub fn vehicles_in_box(&self) -> usize {
+        self.vehicles
+            .iter()
+            .filter(|v| v.is_in_intersection())
+            .count()
+    }
+
+    pub fn queue_length(&self, origin: Direction) -> usize {
+        let waiting = self.queued_vehicles
+            .iter()
+            .filter(|v| v.origin == origin)
+            .count();
+        let approaching = self.vehicles
+            .iter()
+            .filter(|v| v.origin == origin && v.is_approaching())
+            .count();
+        waiting + approaching
+    }
+
+    // Close calls and crashes are counted by both vehicles involved, so these halve the totals
+    pub fn live_close_calls(&self) -> u32 {
+        let active: usize = self.vehicles
+            .iter()
+            .map(|v| v.close_calls.len())
+            .sum();
+        (self.stats.close_calls + (active as u32)) / 2
+    }
+
+    pub fn live_crashes(&self) -> u32 {
+        let active: usize = self.vehicles
+            .iter()
+            .map(|v| v.crashes.len())
+            .sum();
+        (self.stats.crashes + (active as u32)) / 2
+    }
+
     pub fn add_remaining_finished_vehicles(&mut self) {
         self.stats.add_to_total_vehicles(
             self.vehicles
@@ -206,17 +459,41 @@ impl Intersection {
     // Add more methods as needed for intersection behavior
 }
 
+// Stops a vehicle at the edge of the window rather than letting it out through a blocked exit
+fn exit_velocity_limit(car: &Vehicle, blocked_exits: &[Direction]) -> f32 {
+    if !blocked_exits.contains(&car.direction) || !car.is_in_end_lane() {
+        return car.velocity;
+    }
+    let future = car.get_future_position(&car.position);
+    let window = Rect::new(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT);
+    if window.contains_rect(Rect::new(future.x, future.y, car.width, car.height)) {
+        car.velocity
+    } else {
+        0.0
+    }
+}
+
 pub struct Lane {
     pub origin: Direction,
-    pub direction: Direction,
+    pub driving_side: DrivingSide,
+    // Counted from the kerb
+    pub index: usize,
+    pub lane_use: LaneUse,
     pub vehicles: Vec<Vehicle>,
 }
 
 impl Lane {
-    pub fn new(origin: Direction, direction: Direction) -> Self {
+    pub fn new(
+        origin: Direction,
+        driving_side: DrivingSide,
+        index: usize,
+        lane_use: LaneUse
+    ) -> Self {
         Self {
             origin,
-            direction,
+            driving_side,
+            index,
+            lane_use,
             vehicles: Vec::new(),
         }
     }
@@ -225,5 +502,32 @@ impl Lane {
         self.vehicles.push(vehicle);
     }
 
+    // Area of the approach arm this lane occupies, taken from where vehicles enter it
+    pub fn approach_rect(&self) -> Rect {
+        let (spawn, _) = get_lane_position(self.driving_side, self.origin, self.index);
+        match self.origin {
+            Direction::North => Rect::new(spawn.x, 0, LANE_WIDTH, WINDOW_HEIGHT / 3),
+            Direction::South =>
+                Rect::new(spawn.x, ((WINDOW_HEIGHT * 2) / 3) as i32, LANE_WIDTH, WINDOW_HEIGHT / 3),
+            Direction::East =>
+                Rect::new(((WINDOW_WIDTH * 2) / 3) as i32, spawn.y, WINDOW_WIDTH / 3, LANE_WIDTH),
+            Direction::West => Rect::new(0, spawn.y, WINDOW_WIDTH / 3, LANE_WIDTH),
+        }
+    }
+
     // Add more methods as needed for lane-specific behavior
 }
+
+fn build_lanes(layout: &LaneLayout) -> Vec<Lane> {
+    let mut lanes = vec![];
+    for &origin in &layout.arms {
+        if layout.junction == Junction::Roundabout {
+            lanes.push(Lane::new(origin, layout.driving_side, ROUNDABOUT_LANE, LaneUse::ALL));
+            continue;
+        }
+        for (index, lane_use) in layout.lanes(origin).iter().enumerate() {
+            lanes.push(Lane::new(origin, layout.driving_side, index, *lane_use));
+        }
+    }
+    lanes
+}
@@ -0,0 +1,378 @@
+// Just enough JSON for the controller protocol: a value tree, a parser and string quoting
+
+// Deepest nesting of arrays and objects that is parsed, far past what a command needs, so that
+// a line of brackets cannot overflow the stack
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // Members in the order they were written
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { chars: text.chars().collect(), position: 0, depth: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position < parser.chars.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+// A string as a JSON string literal
+pub fn quote(string: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    // Arrays and objects open around the current position
+    depth: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> String {
+        format!("invalid JSON at character {}: {}", self.position, message)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.position).is_some_and(|c| c.is_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.position).copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.peek() != Some(expected) {
+            return Err(self.error(&format!("expected '{}'", expected)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some('{') => self.nested(Parser::object),
+            Some('[') => self.nested(Parser::array),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('n') => self.literal("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Parser) -> Result<Json, String>) -> Result<Json, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            if self.chars.get(self.position) != Some(&expected) {
+                return Err(self.error(&format!("expected '{}'", word)));
+            }
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while
+            self.chars
+                .get(self.position)
+                .is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(*c))
+        {
+            self.position += 1;
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        text.parse::<f64>()
+            .map(Json::Number)
+            .map_err(|_| self.error(&format!("bad number '{}'", text)))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            let c = *self.chars.get(self.position).ok_or(self.error("unterminated string"))?;
+            self.position += 1;
+            match c {
+                '"' => {
+                    return Ok(string);
+                }
+                '\\' => {
+                    let escape = *self.chars
+                        .get(self.position)
+                        .ok_or(self.error("unterminated string"))?;
+                    self.position += 1;
+                    match escape {
+                        '"' | '\\' | '/' => string.push(escape),
+                        'b' => string.push('\u{8}'),
+                        'f' => string.push('\u{c}'),
+                        'n' => string.push('\n'),
+                        'r' => string.push('\r'),
+                        't' => string.push('\t'),
+                        'u' => {
+                            let digits: String = self.chars
+                                .iter()
+                                .skip(self.position)
+                                .take(4)
+                                .collect();
+                            // from_str_radix would also take a sign
+                            if
+                                digits.len() != 4 ||
+                                !digits.chars().all(|c| c.is_ascii_hexdigit())
+                            {
+                                return Err(self.error("bad unicode escape"));
+                            }
+                            let code = u32::from_str_radix(&digits, 16)
+                                .map_err(|_| self.error("bad unicode escape"))?;
+                            self.position += 4;
+                            // Surrogate pairs are not put back together
+                            string.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                        }
+                        _ => {
+                            return Err(self.error("bad escape"));
+                        }
+                    }
+                }
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut items = vec![];
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek() {
+                Some(',') => {
+                    self.position += 1;
+                }
+                Some(']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => {
+                    return Err(self.error("expected ',' or ']'"));
+                }
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut members = vec![];
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            if self.peek() != Some('"') {
+                return Err(self.error("expected a member name"));
+            }
+            let name = self.string()?;
+            self.expect(':')?;
+            members.push((name, self.value()?));
+            match self.peek() {
+                Some(',') => {
+                    self.position += 1;
+                }
+                Some('}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => {
+                    return Err(self.error("expected ',' or '}'"));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_values() {
+        let json = Json::parse(r#" {"a": [1, -2.5e1, true, false, null], "b": {"c": "d"}} "#);
+        assert_eq!(
+            json,
+            Ok(
+                Json::Object(
+                    vec![
+                        (
+                            "a".to_string(),
+                            Json::Array(
+                                vec![
+                                    Json::Number(1.0),
+                                    Json::Number(-25.0),
+                                    Json::Bool(true),
+                                    Json::Bool(false),
+                                    Json::Null
+                                ]
+                            ),
+                        ),
+                        (
+                            "b".to_string(),
+                            Json::Object(vec![("c".to_string(), Json::String("d".to_string()))]),
+                        )
+                    ]
+                )
+            )
+        );
+        assert_eq!(Json::parse("[]"), Ok(Json::Array(vec![])));
+        assert_eq!(Json::parse("{}"), Ok(Json::Object(vec![])));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for text in [
+            "",
+            "   ",
+            "{",
+            "}",
+            "[1,]",
+            "[1 2]",
+            "{\"a\"}",
+            "{\"a\":}",
+            "{a:1}",
+            "{\"a\":1,}",
+            "tru",
+            "nul",
+            "-",
+            "1.2.3",
+            "\"unterminated",
+            "{} {}",
+            "[1] x",
+        ] {
+            assert!(Json::parse(text).is_err(), "{:?} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn parses_escapes() {
+        assert_eq!(
+            Json::parse(r#""\"\\\/\b\f\n\r\t""#),
+            Ok(Json::String("\"\\/\u{8}\u{c}\n\r\t".to_string()))
+        );
+        assert_eq!(Json::parse(r#""\u00e9\u0041""#), Ok(Json::String("éA".to_string())));
+        // Lone surrogates are replaced rather than rejected
+        assert_eq!(Json::parse(r#""\ud800""#), Ok(Json::String("\u{fffd}".to_string())));
+        assert_eq!(Json::parse("\"é\""), Ok(Json::String("é".to_string())));
+    }
+
+    #[test]
+    fn rejects_bad_escapes() {
+        for text in [
+            r#""\x""#,
+            r#""\"#,
+            r#""\u"#,
+            r#""\u12"#,
+            r#""\u12""#,
+            r#""\u+12a""#,
+            r#""\u-001""#,
+            r#""\uzzzz""#,
+        ] {
+            assert!(Json::parse(text).is_err(), "{:?} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Json::parse(&nested(MAX_DEPTH + 1)).is_err());
+        // Far deeper than the stack would take without the limit
+        assert!(Json::parse(&"[".repeat(1_000_000)).is_err());
+        assert!(Json::parse(&"{\"a\":".repeat(1_000_000)).is_err());
+    }
+
+    #[test]
+    fn quotes_strings() {
+        assert_eq!(quote("plain"), "\"plain\"");
+        assert_eq!(quote("a\"b\\c"), r#""a\"b\\c""#);
+        assert_eq!(quote("\n\r\t"), r#""\n\r\t""#);
+        assert_eq!(quote("\u{1}\u{1f}"), r#""\u0001\u001f""#);
+        assert_eq!(quote("é"), "\"é\"");
+    }
+
+    #[test]
+    fn quoted_strings_parse_back() {
+        for string in ["", "plain", "a\"b\\c", "\n\r\t\u{8}\u{c}", "\u{0}\u{1f}", "é ✓"] {
+            assert_eq!(Json::parse(&quote(string)), Ok(Json::String(string.to_string())));
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use std::f32::consts::TAU;
+
+use crate::{ intersection::Direction, WINDOW_WIDTH, WINDOW_HEIGHT };
+
+pub const LANE_WIDTH: u32 = WINDOW_WIDTH / 18;
+// Lanes across each arm counting both ways, those the approach does not use lead away
+pub const LANE_SLOTS: usize = 6;
+pub const MAX_APPROACH_LANES: usize = 5;
+// A T-junction drops one arm, fewer than this is not a junction
+pub const MIN_ARMS: usize = 3;
+// Roundabout arms are one lane each way beside the centre line
+pub const ROUNDABOUT_LANE: usize = 2;
+// Radius of the circulating lane's centre line
+pub const RING_RADIUS: f32 = (WINDOW_WIDTH as f32) / 9.0;
+
+const SIDES: [Direction; 4] = [Direction::North, Direction::South, Direction::East, Direction::West];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Junction {
+    // Arms meet in a box crossed by straight and L-shaped paths
+    Cross,
+    // Arms meet a one-way ring, entering traffic gives way to traffic already on it
+    Roundabout,
+}
+
+impl Junction {
+    pub fn from_name(name: &str) -> Option<Junction> {
+        match name {
+            "cross" => Some(Junction::Cross),
+            "roundabout" => Some(Junction::Roundabout),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Junction::Cross => "cross",
+            Junction::Roundabout => "roundabout",
+        }
+    }
+}
+
+// Side of the road traffic keeps to, which sets where the kerb is and the way round a ring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrivingSide {
+    // Continental, rings are driven anticlockwise
+    Right,
+    // UK, everything mirrored and rings driven clockwise
+    Left,
+}
+
+impl DrivingSide {
+    pub fn from_name(name: &str) -> Option<DrivingSide> {
+        match name {
+            "right" => Some(DrivingSide::Right),
+            "left" => Some(DrivingSide::Left),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DrivingSide::Right => "right",
+            DrivingSide::Left => "left",
+        }
+    }
+
+    // Exit reached by the short turn along the kerb
+    pub fn kerb_turn(&self, origin: Direction) -> Direction {
+        match self {
+            DrivingSide::Right => origin.right_turn(),
+            DrivingSide::Left => origin.right_turn().opposite(),
+        }
+    }
+
+    // Sign of ring_angle changes while driving round a ring
+    pub fn ring_turn(&self) -> f32 {
+        match self {
+            DrivingSide::Right => 1.0,
+            DrivingSide::Left => -1.0,
+        }
+    }
+
+    // Kerb lane turns, the middle goes straight on and the lane by the centre line crosses
+    pub fn default_lanes(&self) -> Vec<LaneUse> {
+        match self {
+            DrivingSide::Right => vec![LaneUse::RIGHT, LaneUse::STRAIGHT, LaneUse::LEFT],
+            DrivingSide::Left => vec![LaneUse::LEFT, LaneUse::STRAIGHT, LaneUse::RIGHT],
+        }
+    }
+}
+
+// Movements a vehicle may make from a lane
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LaneUse {
+    pub left: bool,
+    pub straight: bool,
+    pub right: bool,
+}
+
+impl LaneUse {
+    pub const LEFT: LaneUse = LaneUse { left: true, straight: false, right: false };
+    pub const STRAIGHT: LaneUse = LaneUse { left: false, straight: true, right: false };
+    pub const RIGHT: LaneUse = LaneUse { left: false, straight: false, right: true };
+    pub const ALL: LaneUse = LaneUse { left: true, straight: true, right: true };
+
+    // Movements joined with '+', such as "straight+right" for a shared lane
+    pub fn from_name(name: &str) -> Option<LaneUse> {
+        let mut lane_use = LaneUse { left: false, straight: false, right: false };
+        for movement in name.split('+').map(|m| m.trim()) {
+            match movement {
+                "left" => {
+                    lane_use.left = true;
+                }
+                "straight" => {
+                    lane_use.straight = true;
+                }
+                "right" => {
+                    lane_use.right = true;
+                }
+                _ => {
+                    return None;
+                }
+            }
+        }
+        Some(lane_use)
+    }
+
+    pub fn allows(&self, origin: Direction, direction: Direction) -> bool {
+        if direction == origin.right_turn() {
+            self.right
+        } else if direction == origin.opposite() {
+            self.straight
+        } else {
+            direction != origin && self.left
+        }
+    }
+}
+
+// Lanes of every approach, each listed from the kerb towards the centre line. A roundabout
+// keeps the lanes for when it is switched back but only drives on ROUNDABOUT_LANE, and a
+// missing arm keeps them for when it is put back.
+#[derive(Debug, Clone)]
+pub struct LaneLayout {
+    pub junction: Junction,
+    pub driving_side: DrivingSide,
+    pub approaches: [Vec<LaneUse>; 4],
+    // Sides with a road, all four for a cross and three for a T-junction. Only the four sides
+    // exist, as vehicles are upright rectangles facing one of them, so arms meeting at other
+    // angles are not supported.
+    pub arms: Vec<Direction>,
+}
+
+impl LaneLayout {
+    pub fn new() -> Self {
+        let driving_side = DrivingSide::Right;
+        let lanes = driving_side.default_lanes();
+        Self {
+            junction: Junction::Cross,
+            driving_side,
+            approaches: [lanes.clone(), lanes.clone(), lanes.clone(), lanes],
+            arms: SIDES.to_vec(),
+        }
+    }
+
+    pub fn has_arm(&self, side: Direction) -> bool {
+        self.arms.contains(&side)
+    }
+
+    // Lanes in use, with each roundabout approach a single lane for every movement
+    pub fn lanes(&self, origin: Direction) -> &[LaneUse] {
+        if !self.has_arm(origin) {
+            return &[];
+        }
+        match self.junction {
+            Junction::Cross => &self.approaches[side_index(origin)],
+            Junction::Roundabout => &[LaneUse::ALL],
+        }
+    }
+
+    pub fn set_lanes(&mut self, origin: Direction, lanes: Vec<LaneUse>) {
+        self.approaches[side_index(origin)] = lanes;
+    }
+
+    // Approaches still on the default lanes get the mirrored default for the new side
+    pub fn set_driving_side(&mut self, driving_side: DrivingSide) {
+        let old_default = self.driving_side.default_lanes();
+        for lanes in self.approaches.iter_mut().filter(|lanes| **lanes == old_default) {
+            *lanes = driving_side.default_lanes();
+        }
+        self.driving_side = driving_side;
+    }
+
+    // Lanes leaving by this side are the slots its approach does not use
+    pub fn exit_lanes(&self, side: Direction) -> usize {
+        if !self.has_arm(side) {
+            return 0;
+        }
+        LANE_SLOTS - self.lanes(side).len()
+    }
+
+    pub fn legal_lanes(&self, origin: Direction, direction: Direction) -> Vec<usize> {
+        if !self.has_arm(origin) || !self.has_arm(direction) {
+            return vec![];
+        }
+        if self.junction == Junction::Roundabout {
+            return vec![ROUNDABOUT_LANE];
+        }
+        self.lanes(origin)
+            .iter()
+            .enumerate()
+            .filter(|(_, lane_use)| lane_use.allows(origin, direction))
+            .map(|(lane, _)| lane)
+            .collect()
+    }
+
+    // Lane a vehicle ends up in after leaving the box. Going straight keeps to the same line,
+    // turning vehicles keep their place counted from the kerb where the exit has room for it.
+    // None when there is no arm to leave by.
+    pub fn exit_lane(&self, origin: Direction, direction: Direction, lane: usize) -> Option<usize> {
+        if !self.has_arm(direction) {
+            return None;
+        }
+        if self.junction == Junction::Roundabout || direction == origin.opposite() {
+            Some(lane)
+        } else {
+            self.exit_lanes(direction)
+                .checked_sub(1)
+                .map(|last| lane.min(last))
+        }
+    }
+
+    // Exits a vehicle can leave by after arriving from origin
+    pub fn exits(&self, origin: Direction) -> Vec<Direction> {
+        self.arms
+            .iter()
+            .filter(|&&side| side != origin)
+            .copied()
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.arms.len() < MIN_ARMS {
+            return Err(format!("a junction needs at least {} arms", MIN_ARMS));
+        }
+        for &origin in &self.arms {
+            let count = self.approaches[side_index(origin)].len();
+            if count == 0 || count > MAX_APPROACH_LANES {
+                return Err(
+                    format!(
+                        "{:?} approach has {} lanes, it needs 1 to {}",
+                        origin,
+                        count,
+                        MAX_APPROACH_LANES
+                    )
+                );
+            }
+            // Straight on traffic keeps its line, so it needs a lane leaving the far side
+            if
+                self.has_arm(origin.opposite()) &&
+                count + self.approaches[side_index(origin.opposite())].len() > LANE_SLOTS
+            {
+                return Err(
+                    format!(
+                        "{:?} and {:?} approaches need more than {} lanes between them",
+                        origin,
+                        origin.opposite(),
+                        LANE_SLOTS
+                    )
+                );
+            }
+            for direction in self.exits(origin) {
+                let lanes = &self.approaches[side_index(origin)];
+                if !lanes.iter().any(|lane_use| lane_use.allows(origin, direction)) {
+                    return Err(format!("no lane from {:?} goes {:?}", origin, direction));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn side_index(side: Direction) -> usize {
+    SIDES.iter()
+        .position(|&s| s == side)
+        .unwrap_or(0)
+}
+
+// Left or top edge of an approach lane, counted from the kerb. This is x on the north and
+// south arms and y on the east and west arms. Driving on the left puts each approach where
+// the opposite approach's lanes are when driving on the right.
+pub fn approach_lane_offset(driving_side: DrivingSide, origin: Direction, lane: usize) -> i32 {
+    let origin = match driving_side {
+        DrivingSide::Right => origin,
+        DrivingSide::Left => origin.opposite(),
+    };
+    let lane_offset = (LANE_WIDTH as i32) * (lane as i32);
+    match origin {
+        Direction::North => (WINDOW_WIDTH as i32) / 3 + lane_offset,
+        Direction::South => ((WINDOW_WIDTH as i32) * 2) / 3 - (LANE_WIDTH as i32) - lane_offset,
+        Direction::East => (WINDOW_HEIGHT as i32) / 3 + lane_offset,
+        Direction::West => ((WINDOW_HEIGHT as i32) * 2) / 3 - (LANE_WIDTH as i32) - lane_offset,
+    }
+}
+
+// Traffic leaving by a side drives the same way as traffic approaching from the opposite one
+pub fn exit_lane_offset(driving_side: DrivingSide, side: Direction, lane: usize) -> i32 {
+    approach_lane_offset(driving_side, side.opposite(), lane)
+}
+
+// The kerb lane's kerb is its left or top edge, the way lanes are counted outwards
+pub fn is_kerb_at_offset(driving_side: DrivingSide, origin: Direction) -> bool {
+    approach_lane_offset(driving_side, origin, 1) > approach_lane_offset(driving_side, origin, 0)
+}
+
+pub fn ring_centre() -> (f32, f32) {
+    ((WINDOW_WIDTH as f32) / 2.0, (WINDOW_HEIGHT as f32) / 2.0)
+}
+
+// Anticlockwise angle of a point around the ring centre as seen on screen, from east
+pub fn ring_angle(x: f32, y: f32) -> f32 {
+    let (centre_x, centre_y) = ring_centre();
+    (centre_y - y).atan2(x - centre_x).rem_euclid(TAU)
+}
+
+pub fn ring_point(angle: f32) -> (f32, f32) {
+    let (centre_x, centre_y) = ring_centre();
+    (centre_x + RING_RADIUS * angle.cos(), centre_y - RING_RADIUS * angle.sin())
+}
+
+// Where a line along an arm, at the given x on north and south arms or y on east and west
+// arms, meets the ring on that arm's side
+pub fn ring_point_on_arm(side: Direction, across: f32) -> (f32, f32) {
+    let (centre_x, centre_y) = ring_centre();
+    let offset = match side {
+        Direction::North | Direction::South => across - centre_x,
+        Direction::East | Direction::West => across - centre_y,
+    };
+    let along = (RING_RADIUS * RING_RADIUS - offset * offset).max(0.0).sqrt();
+    match side {
+        Direction::North => (across, centre_y - along),
+        Direction::South => (across, centre_y + along),
+        Direction::East => (centre_x + along, across),
+        Direction::West => (centre_x - along, across),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_limits_lanes_per_approach() {
+        let mut layout = LaneLayout::new();
+        assert!(layout.validate().is_ok());
+        layout.set_lanes(Direction::North, vec![LaneUse::ALL; MAX_APPROACH_LANES + 1]);
+        assert!(layout.validate().is_err());
+        layout.set_lanes(Direction::North, vec![]);
+        assert!(layout.validate().is_err());
+    }
+
+    #[test]
+    fn validate_limits_lanes_between_opposite_approaches() {
+        let mut layout = LaneLayout::new();
+        layout.set_lanes(Direction::North, vec![LaneUse::ALL; 4]);
+        layout.set_lanes(Direction::South, vec![LaneUse::ALL; 2]);
+        assert!(layout.validate().is_ok());
+        layout.set_lanes(Direction::South, vec![LaneUse::ALL; 3]);
+        assert!(layout.validate().is_err());
+
+        // Without the opposite arm nothing has to line up across the junction
+        layout.arms.retain(|&side| side != Direction::South);
+        assert!(layout.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_checks_movements_against_arms() {
+        let mut layout = LaneLayout::new();
+        layout.arms.retain(|&side| side != Direction::North);
+        // Lanes may still allow the turn towards the missing arm, and its own lanes are unused
+        layout.set_lanes(Direction::North, vec![]);
+        assert!(layout.validate().is_ok());
+        assert_eq!(layout.exit_lane(Direction::West, Direction::North, 2), None);
+        assert_eq!(layout.exit_lane(Direction::West, Direction::South, 2), Some(2));
+
+        // Every arm that is there has to be reachable
+        layout.set_lanes(Direction::West, vec![LaneUse::STRAIGHT, LaneUse::LEFT]);
+        assert!(layout.validate().is_err());
+
+        // Swapping the missing arm leaves the West lanes enough, once North has lanes again
+        layout.arms.push(Direction::North);
+        layout.arms.retain(|&side| side != Direction::South);
+        assert!(layout.validate().is_err());
+        layout.set_lanes(Direction::North, DrivingSide::Right.default_lanes());
+        assert!(layout.validate().is_ok());
+    }
+
+    #[test]
+    fn driving_side_mirrors_default_lanes() {
+        let mut layout = LaneLayout::new();
+        let shared = vec![LaneUse { left: false, straight: true, right: true }, LaneUse::LEFT];
+        layout.set_lanes(Direction::East, shared.clone());
+        layout.set_driving_side(DrivingSide::Left);
+        assert!(layout.validate().is_ok());
+        assert_eq!(layout.lanes(Direction::North), DrivingSide::Left.default_lanes());
+        assert_eq!(layout.lanes(Direction::East), shared);
+
+        // The kerb lane still turns along the kerb, now to the left
+        assert_eq!(DrivingSide::Left.kerb_turn(Direction::North), Direction::East);
+        assert_eq!(layout.legal_lanes(Direction::North, Direction::East), vec![0]);
+        assert_eq!(layout.legal_lanes(Direction::North, Direction::West), vec![2]);
+
+        layout.set_driving_side(DrivingSide::Right);
+        assert_eq!(layout.lanes(Direction::North), DrivingSide::Right.default_lanes());
+    }
+}
@@ -0,0 +1,30 @@
+// The simulation without its window, shared by both binaries and usable from other crates.
+// Nothing here needs SDL2, key bindings in the config aside when the gui feature is on.
+
+// Types are built with new() throughout
+#![allow(clippy::new_without_default)]
+
+pub mod geometry;
+pub mod intersection;
+pub mod vehicle;
+pub mod physics;
+pub mod algorithm;
+pub mod statistics;
+pub mod heatmap;
+pub mod config;
+pub mod pedestrian;
+pub mod network;
+pub mod routing;
+pub mod layout;
+pub mod conflict;
+pub mod platoon;
+pub mod schedule;
+pub mod auction;
+pub mod environment;
+pub mod json;
+pub mod controller;
+
+pub const WINDOW_WIDTH: u32 = 600;
+pub const WINDOW_HEIGHT: u32 = 600;
+// 800ms between continuous spawns at the 60Hz simulation rate, unless the config says otherwise
+pub const SPAWN_INTERVAL_TICKS: u64 = 48;
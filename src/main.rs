@@ -1,28 +1,38 @@
 mod render;
-mod intersection;
-mod vehicle;
-mod physics;
-mod algorithm;
-mod statistics;
+mod capture;
 
-use crate::render::render;
-use std::time::{ Duration, Instant };
+use std::{ collections::HashMap, time::{ Duration, Instant } };
 
-use intersection::Intersection;
 use sdl2::{
     event::Event,
-    keyboard::Keycode,
+    mouse::MouseButton,
     render::{ TextureCreator, Texture },
     video::WindowContext,
     image::LoadTexture,
 };
+use smart_road::{
+    geometry::Point,
+    intersection::Direction,
+    network::Network,
+    heatmap::HeatmapMetric,
+    config::{ Config, Action, CONFIG_PATH },
+    environment::{ Command, apply_command },
+    controller::{ Controller, Reply },
+    vehicle::VehicleKind,
+    WINDOW_WIDTH,
+    WINDOW_HEIGHT,
+};
+
+use crate::{ render::{ render, RenderOptions }, capture::FrameCapture };
 
-const WINDOW_WIDTH: u32 = 600;
-const WINDOW_HEIGHT: u32 = 600;
-const KEY_PRESS_INTERVAL: Duration = Duration::from_millis(0);
-const SPAWN_INTERVAL: Duration = Duration::from_millis(800);
+const TIME_SCALES: [f32; 7] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0];
+const DEFAULT_TIME_SCALE_INDEX: usize = 2;
+// Every other frame gives a 30fps frame sequence
+const CAPTURE_EVERY_N_FRAMES: u32 = 2;
 
 fn main() -> Result<(), String> {
+    let config = Config::load(CONFIG_PATH)?;
+
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
     let ttf_context = sdl2::ttf::init().unwrap();
@@ -42,15 +52,41 @@ fn main() -> Result<(), String> {
     let texture_creator = canvas.texture_creator();
     let car_texture = create_car_texture(&texture_creator);
     let road_texture = create_road_texture(&texture_creator);
+    let vehicle_textures = create_vehicle_textures(&texture_creator);
 
     let mut last_keypress_time = Instant::now();
-    let mut last_spawn_time = Instant::now();
+    let mut last_spawn_tick: u64 = 0;
 
-    let mut intersection = Intersection::new();
+    let mut network = Network::new(&config.network);
+    for intersection in &mut network.intersections {
+        intersection.vehicle_mix = config.vehicle_mix.clone();
+        intersection.pedestrian_demand = config.pedestrian_demand.clone();
+        intersection.manager = config.manager;
+        intersection.max_wait = config.max_wait;
+    }
+    network.set_layout(&config.lanes);
+    if let Some(seed) = config.seed {
+        network.seed(seed);
+    }
+    let mut controller = match config.controller.address {
+        Some(_) => Some(Controller::listen(&config.controller, &network, false)?),
+        None => None,
+    };
     let mut vehicle_id = 0;
 
     let mut continuous_spawning: bool = false;
-    let mut show_statistics: bool = false;
+    let mut render_options = RenderOptions {
+        show_statistics: false,
+        show_hud: true,
+        show_debug: false,
+        heatmap: None,
+        selected_vehicle: None,
+    };
+    let mut paused: bool = false;
+    let mut step_requested: bool = false;
+    let mut time_scale_index = DEFAULT_TIME_SCALE_INDEX;
+    let mut tick_accumulator: f32 = 0.0;
+    let mut frame_capture = FrameCapture::new(CAPTURE_EVERY_N_FRAMES);
 
     let mut event_pump = sdl_context.event_pump()?;
     'running: loop {
@@ -60,30 +96,106 @@ fn main() -> Result<(), String> {
                 Event::Quit { .. } => {
                     break 'running;
                 }
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    if show_statistics {
-                        // Handle the event when Escape is pressed and show_statistics is true
-                        break 'running;
-                    } else {
-                        show_statistics = true;
-                        //intersection.add_remaining_finished_vehicles();
-                        //intersection.find_min_max_times();
-                    }
-                }
                 Event::KeyDown { keycode: Some(keycode), .. } => {
                     let elapsed_time = Instant::now().duration_since(last_keypress_time);
-                    if elapsed_time >= KEY_PRESS_INTERVAL {
-                        match keycode {
-                            Keycode::Left | Keycode::Right | Keycode::Up | Keycode::Down => {
-                                intersection.add_directed_vehicle(keycode, vehicle_id);
-                                vehicle_id += 1;
+                    if elapsed_time < config.bindings.key_press_interval {
+                        continue;
+                    }
+                    last_keypress_time = Instant::now();
+
+                    match config.bindings.action_for(keycode) {
+                        Some(Action::Quit) => {
+                            if render_options.show_statistics {
+                                // Quit is pressed a second time while statistics are shown
+                                break 'running;
+                            } else {
+                                render_options.show_statistics = true;
                             }
-                            Keycode::R => {
-                                continuous_spawning = !continuous_spawning;
+                        }
+                        Some(Action::SpawnFromNorth) => {
+                            network.add_routed_vehicle(network.focused, Direction::North, vehicle_id);
+                            vehicle_id += 1;
+                        }
+                        Some(Action::SpawnFromSouth) => {
+                            network.add_routed_vehicle(network.focused, Direction::South, vehicle_id);
+                            vehicle_id += 1;
+                        }
+                        Some(Action::SpawnFromEast) => {
+                            network.add_routed_vehicle(network.focused, Direction::East, vehicle_id);
+                            vehicle_id += 1;
+                        }
+                        Some(Action::SpawnFromWest) => {
+                            network.add_routed_vehicle(network.focused, Direction::West, vehicle_id);
+                            vehicle_id += 1;
+                        }
+                        Some(Action::SpawnEmergency) => {
+                            network.focused_mut().add_emergency_vehicle(vehicle_id);
+                            vehicle_id += 1;
+                        }
+                        Some(Action::ToggleContinuousSpawn) => {
+                            continuous_spawning = !continuous_spawning;
+                        }
+                        Some(Action::ToggleHud) => {
+                            render_options.show_hud = !render_options.show_hud;
+                        }
+                        Some(Action::ToggleDebug) => {
+                            render_options.show_debug = !render_options.show_debug;
+                        }
+                        Some(Action::CycleHeatmap) => {
+                            render_options.heatmap = HeatmapMetric::next(render_options.heatmap);
+                        }
+                        Some(Action::ExportHeatmap) => {
+                            for metric in [
+                                HeatmapMetric::Delay,
+                                HeatmapMetric::CloseCalls,
+                                HeatmapMetric::Crashes,
+                            ] {
+                                let path = format!("heatmap_{}.png", metric.name());
+                                if let Err(e) = network.focused().heatmap.export_png(metric, &path) {
+                                    eprintln!("could not export {}: {}", path, e);
+                                }
                             }
-                            _ => {}
                         }
-                        last_keypress_time = Instant::now();
+                        Some(Action::Screenshot) => {
+                            frame_capture.request_screenshot();
+                        }
+                        Some(Action::ToggleRecording) => {
+                            if let Err(e) = frame_capture.toggle_sequence() {
+                                eprintln!("could not start recording: {}", e);
+                            }
+                        }
+                        Some(Action::Pause) => {
+                            paused = !paused;
+                            tick_accumulator = 0.0;
+                        }
+                        // Advancing a single tick only makes sense while paused
+                        Some(Action::Step) if paused => {
+                            step_requested = true;
+                        }
+                        Some(Action::SlowDown) => {
+                            time_scale_index = time_scale_index.saturating_sub(1);
+                        }
+                        Some(Action::SpeedUp) => {
+                            time_scale_index = (time_scale_index + 1).min(TIME_SCALES.len() - 1);
+                        }
+                        _ => {}
+                    }
+                }
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                    // Clicking focuses that intersection, then a vehicle inspects it and an
+                    // approach lane spawns into it
+                    if let Some((index, point)) = network.locate(Point::new(x, y)) {
+                        network.focused = index;
+                        let intersection = network.focused_mut();
+                        render_options.selected_vehicle = intersection
+                            .vehicle_at(point)
+                            .map(|v| v.id);
+                        if
+                            render_options.selected_vehicle.is_none() &&
+                            intersection.add_vehicle_at(point, vehicle_id)
+                        {
+                            vehicle_id += 1;
+                        }
                     }
                 }
 
@@ -91,22 +203,76 @@ fn main() -> Result<(), String> {
             }
         }
 
-        // Update
-        if !show_statistics {
-            let elapsed_spawn_time = Instant::now().duration_since(last_spawn_time);
-            if continuous_spawning && elapsed_spawn_time >= SPAWN_INTERVAL {
-                intersection.add_random_vehicle(vehicle_id);
-                vehicle_id += 1;
-                last_spawn_time = Instant::now();
+        // Update, always in whole fixed ticks so the outcome does not depend on the speed
+        if !render_options.show_statistics {
+            let mut ticks_to_run = 0;
+            if paused {
+                if step_requested {
+                    ticks_to_run = 1;
+                }
+            } else {
+                tick_accumulator += TIME_SCALES[time_scale_index];
+                while tick_accumulator >= 1.0 {
+                    ticks_to_run += 1;
+                    tick_accumulator -= 1.0;
+                }
+            }
+            step_requested = false;
+
+            for _ in 0..ticks_to_run {
+                if
+                    continuous_spawning &&
+                    network.tick() - last_spawn_tick >= config.spawn_interval_ticks
+                {
+                    network.add_random_vehicle(vehicle_id);
+                    vehicle_id += 1;
+                    last_spawn_tick = network.tick();
+                }
+                // A controller that goes away leaves the vehicles to drive on without commands
+                if let Some(connected) = &mut controller {
+                    match connected.exchange(&network, None) {
+                        Ok(Reply::Command(command)) => apply_command(&mut network, &command),
+                        // Refused without episodes, only the headless binary has them
+                        Ok(Reply::Reset(_)) => {}
+                        Err(e) => {
+                            eprintln!("controller: {}", e);
+                            apply_command(&mut network, &Command::new());
+                            controller = None;
+                        }
+                    }
+                }
+                network.update();
             }
-            intersection.add_vehicle();
-            intersection.update();
         }
 
+        canvas
+            .window_mut()
+            .set_title(
+                &format!(
+                    "Smart Road - {}x{}{}",
+                    TIME_SCALES[time_scale_index],
+                    if paused { " (paused)" } else { "" },
+                    if frame_capture.is_recording() { " (recording)" } else { "" }
+                )
+            )
+            .map_err(|e| e.to_string())?;
+
         // Render
-        render(&mut canvas, &intersection, &car_texture, &road_texture, &font, show_statistics)?;
+        render(
+            &mut canvas,
+            &network,
+            &car_texture,
+            &vehicle_textures,
+            &road_texture,
+            &font,
+            render_options
+        )?;
+        if let Err(e) = frame_capture.capture(&canvas) {
+            eprintln!("could not capture frame: {}", e);
+        }
+        canvas.present();
 
-        // Time management!
+        // Time management! Frames stay at 60Hz, the time scale only changes ticks per frame
         ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
     }
 
@@ -122,3 +288,21 @@ fn create_road_texture(texture_creator: &TextureCreator<WindowContext>) -> Textu
     let path = format!("src/assets/2112_w032_n003_284b_p1_284.jpg");
     texture_creator.load_texture(&path).expect(&format!("could not load texture: {}", path))
 }
+
+// Kinds whose sprite cannot be loaded are drawn with the car texture instead
+fn create_vehicle_textures(
+    texture_creator: &TextureCreator<WindowContext>
+) -> HashMap<VehicleKind, Texture<'_>> {
+    let mut textures = HashMap::new();
+    for kind in VehicleKind::ALL {
+        if let Some(path) = kind.sprite_path() {
+            match texture_creator.load_texture(path) {
+                Ok(texture) => {
+                    textures.insert(kind, texture);
+                }
+                Err(e) => eprintln!("could not load texture: {}: {}", path, e),
+            }
+        }
+    }
+    textures
+}
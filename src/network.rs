@@ -0,0 +1,240 @@
+use rand::{ rngs::StdRng, Rng, SeedableRng };
+
+use crate::{
+    geometry::Point,
+    intersection::{ Intersection, Direction },
+    routing::{ RoadGraph, RouteCost, approach_index },
+    layout::{ Junction, LaneLayout },
+    WINDOW_WIDTH,
+    WINDOW_HEIGHT,
+};
+
+#[derive(Debug, Clone)]
+pub struct NetworkLayout {
+    pub rows: usize,
+    pub columns: usize,
+    pub link_capacity: usize,
+    pub route_cost: RouteCost,
+    // Run a cross and a roundabout side by side with the same arrivals instead of a grid
+    pub compare_junctions: bool,
+}
+
+impl NetworkLayout {
+    pub fn new() -> Self {
+        Self {
+            rows: 1,
+            columns: 1,
+            link_capacity: 6,
+            route_cost: RouteCost::TravelTime,
+            compare_junctions: false,
+        }
+    }
+}
+
+// Grid of intersections where each exit lane feeds the facing approach of the neighbour
+pub struct Network {
+    pub rows: usize,
+    pub columns: usize,
+    pub intersections: Vec<Intersection>,
+    pub focused: usize,
+    // Vehicles queued on a link before its exit is closed, so queues spill back upstream
+    pub link_capacity: usize,
+    pub vehicles_left_network: u32,
+    pub graph: RoadGraph,
+    pub route_cost: RouteCost,
+    // Intersections are not linked and every arrival is copied to all of them
+    pub mirrored: bool,
+    // Draws where arrivals enter and which route they take, each intersection has its own
+    pub rng: StdRng,
+}
+
+impl Network {
+    pub fn new(layout: &NetworkLayout) -> Self {
+        let mirrored = layout.compare_junctions;
+        let (rows, columns) = if mirrored {
+            (1, 2)
+        } else {
+            (layout.rows.max(1), layout.columns.max(1))
+        };
+        let mut network = Self {
+            rows,
+            columns,
+            intersections: (0..rows * columns).map(|_| Intersection::new()).collect(),
+            focused: 0,
+            link_capacity: layout.link_capacity,
+            vehicles_left_network: 0,
+            graph: RoadGraph::new(&[], |_, _| None),
+            route_cost: layout.route_cost,
+            mirrored,
+            rng: StdRng::from_entropy(),
+        };
+        network.build_graph();
+        network
+    }
+
+    // Links are measured on each intersection's layout, so the graph follows layout changes
+    fn build_graph(&mut self) {
+        self.graph = RoadGraph::new(&self.intersections, |index, side| {
+            self.neighbour(index, side)
+        });
+    }
+
+    // Reseeds every random draw from one seed, so the same arrivals and choices come again
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+        for intersection in &mut self.intersections {
+            intersection.rng = StdRng::seed_from_u64(self.rng.gen());
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.intersections[0].tick
+    }
+
+    pub fn focused(&self) -> &Intersection {
+        &self.intersections[self.focused]
+    }
+
+    pub fn focused_mut(&mut self) -> &mut Intersection {
+        &mut self.intersections[self.focused]
+    }
+
+    pub fn neighbour(&self, index: usize, side: Direction) -> Option<usize> {
+        if self.mirrored {
+            return None;
+        }
+        let (row, column) = (index / self.columns, index % self.columns);
+        match side {
+            Direction::North if row > 0 => Some(index - self.columns),
+            Direction::South if row + 1 < self.rows => Some(index + self.columns),
+            Direction::West if column > 0 => Some(index - 1),
+            Direction::East if column + 1 < self.columns => Some(index + 1),
+            _ => None,
+        }
+    }
+
+    // Neighbour that an exit leads into, None when that neighbour has no arm facing back so
+    // the road leaves the network there
+    pub fn linked_neighbour(&self, index: usize, side: Direction) -> Option<usize> {
+        self.neighbour(index, side).filter(|&next| {
+            self.intersections[next].layout.has_arm(side.opposite())
+        })
+    }
+
+    // Approaches on the edge of the grid, the only places traffic can enter from outside
+    pub fn entry_points(&self) -> Vec<(usize, Direction)> {
+        let mut entries = vec![];
+        for index in 0..self.intersections.len() {
+            for side in [Direction::North, Direction::South, Direction::East, Direction::West] {
+                if
+                    self.neighbour(index, side).is_none() &&
+                    self.intersections[index].layout.has_arm(side)
+                {
+                    entries.push((index, side));
+                }
+            }
+        }
+        entries
+    }
+
+    pub fn add_random_vehicle(&mut self, id: u32) {
+        let entries = self.entry_points();
+        let (index, origin) = entries[self.rng.gen_range(0..entries.len())];
+        self.add_routed_vehicle(index, origin, id);
+    }
+
+    // Spawns a vehicle at an approach and routes it to a random exit off the network, other
+    // than straight back out where it came in. Nothing arrives where a junction has no arm.
+    pub fn add_routed_vehicle(&mut self, index: usize, origin: Direction, id: u32) {
+        if !self.intersections[index].layout.has_arm(origin) {
+            return;
+        }
+        let kind = self.intersections[index].vehicle_mix.pick(&mut self.rng, |_| true);
+        let mut routes = self.graph.routes_from(
+            approach_index(index, origin),
+            kind,
+            self.route_cost,
+            &self.intersections
+        );
+        if routes.len() > 1 {
+            routes.retain(|r| r.leaves_at != (index, origin));
+        }
+        if routes.is_empty() {
+            return;
+        }
+        let route = routes.swap_remove(self.rng.gen_range(0..routes.len()));
+        if self.mirrored {
+            for intersection in &mut self.intersections {
+                intersection.add_routed_vehicle(origin, kind, route.exits.clone(), id);
+            }
+        } else {
+            self.intersections[index].add_routed_vehicle(origin, kind, route.exits, id);
+        }
+    }
+
+    // When comparing, the first intersection stays a cross and the second is a roundabout
+    pub fn set_layout(&mut self, layout: &LaneLayout) {
+        for (index, intersection) in self.intersections.iter_mut().enumerate() {
+            let mut layout = layout.clone();
+            if self.mirrored {
+                layout.junction = if index == 0 { Junction::Cross } else { Junction::Roundabout };
+            }
+            intersection.set_layout(layout);
+        }
+        self.build_graph();
+    }
+
+    pub fn update(&mut self) {
+        // Close exits whose downstream link is full before anything moves
+        for index in 0..self.intersections.len() {
+            let mut blocked = vec![];
+            for side in [Direction::North, Direction::South, Direction::East, Direction::West] {
+                if let Some(next) = self.neighbour(index, side) {
+                    let queued = self.intersections[next].queue_length(side.opposite());
+                    if queued >= self.link_capacity {
+                        blocked.push(side);
+                    }
+                }
+            }
+            self.intersections[index].blocked_exits = blocked;
+        }
+
+        for intersection in &mut self.intersections {
+            intersection.add_vehicle();
+            intersection.update();
+        }
+
+        // Hand vehicles that left through an exit over to the next intersection
+        for index in 0..self.intersections.len() {
+            let departed = std::mem::take(&mut self.intersections[index].departed);
+            for vehicle in departed {
+                match self.linked_neighbour(index, vehicle.direction) {
+                    Some(next) => {
+                        self.intersections[next].add_continuing_vehicle(&vehicle);
+                    }
+                    None => {
+                        self.vehicles_left_network += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // Which intersection a window point falls on, and the point in that intersection's space
+    pub fn locate(&self, point: Point) -> Option<(usize, Point)> {
+        let tile_width = (WINDOW_WIDTH as i32) / (self.columns as i32);
+        let tile_height = (WINDOW_HEIGHT as i32) / (self.rows as i32);
+        if point.x < 0 || point.y < 0 {
+            return None;
+        }
+        let (column, row) = (point.x / tile_width, point.y / tile_height);
+        if column >= (self.columns as i32) || row >= (self.rows as i32) {
+            return None;
+        }
+        let local = Point::new(
+            (point.x - column * tile_width) * (self.columns as i32),
+            (point.y - row * tile_height) * (self.rows as i32)
+        );
+        Some(((row as usize) * self.columns + (column as usize), local))
+    }
+}
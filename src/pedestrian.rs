@@ -0,0 +1,248 @@
+use rand::Rng;
+
+use crate::{
+    geometry::{ Point, Rect },
+    intersection::Direction,
+    vehicle::Vehicle,
+    WINDOW_WIDTH,
+    WINDOW_HEIGHT,
+};
+
+pub const CROSSING_WIDTH: u32 = 12;
+pub const PEDESTRIAN_SIZE: u32 = 6;
+// Pixels per tick, about a third of a car's cruising speed
+const PEDESTRIAN_SPEED: i32 = 1;
+
+// Zebra crossing over one arm, just outside the box
+pub struct Crossing {
+    pub arm: Direction,
+    pub rect: Rect,
+}
+
+impl Crossing {
+    pub fn new(arm: Direction) -> Self {
+        let third_width = (WINDOW_WIDTH / 3) as i32;
+        let third_height = (WINDOW_HEIGHT / 3) as i32;
+        let crossing_width = CROSSING_WIDTH as i32;
+        let rect = match arm {
+            Direction::North =>
+                Rect::new(
+                    third_width,
+                    third_height - crossing_width,
+                    WINDOW_WIDTH / 3,
+                    CROSSING_WIDTH
+                ),
+            Direction::South =>
+                Rect::new(third_width, third_height * 2, WINDOW_WIDTH / 3, CROSSING_WIDTH),
+            Direction::East =>
+                Rect::new(third_width * 2, third_height, CROSSING_WIDTH, WINDOW_HEIGHT / 3),
+            Direction::West =>
+                Rect::new(
+                    third_width - crossing_width,
+                    third_height,
+                    CROSSING_WIDTH,
+                    WINDOW_HEIGHT / 3
+                ),
+        };
+        Self { arm, rect }
+    }
+
+    pub fn is_occupied(&self, pedestrians: &[Pedestrian]) -> bool {
+        pedestrians.iter().any(|p| p.arm == self.arm && !p.waiting)
+    }
+
+    fn has_vehicle_on(&self, vehicles: &[Vehicle]) -> bool {
+        vehicles.iter().any(|v| v.get_rect().has_intersection(self.rect))
+    }
+
+    // A vehicle past its stop line leaving by this arm cannot be held any more, so the
+    // crossing stays closed to pedestrians until it has driven over
+    fn has_vehicle_heading_for(&self, vehicles: &[Vehicle]) -> bool {
+        vehicles
+            .iter()
+            .filter(|v| v.direction == self.arm && v.get_distance_to_stop_line() < 0)
+            .any(|v| {
+                let rect = v.get_rect();
+                match self.arm {
+                    Direction::North => rect.bottom() > self.rect.top(),
+                    Direction::South => rect.top() < self.rect.bottom(),
+                    Direction::East => rect.left() < self.rect.right(),
+                    Direction::West => rect.right() > self.rect.left(),
+                }
+            })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pedestrian {
+    pub arm: Direction,
+    pub position: Point,
+    pub step: Point,
+    pub waiting: bool,
+    pub wait_time: f32,
+}
+
+impl Pedestrian {
+    // Arrives on the kerb at either end of the crossing
+    pub fn new(crossing: &Crossing, from_start: bool) -> Self {
+        let rect = crossing.rect;
+        let size = PEDESTRIAN_SIZE as i32;
+        let (position, step) = match crossing.arm {
+            Direction::North | Direction::South => {
+                let y = rect.y() + ((CROSSING_WIDTH as i32) - size) / 2;
+                if from_start {
+                    (Point::new(rect.left() - size, y), Point::new(PEDESTRIAN_SPEED, 0))
+                } else {
+                    (Point::new(rect.right(), y), Point::new(-PEDESTRIAN_SPEED, 0))
+                }
+            }
+            Direction::East | Direction::West => {
+                let x = rect.x() + ((CROSSING_WIDTH as i32) - size) / 2;
+                if from_start {
+                    (Point::new(x, rect.top() - size), Point::new(0, PEDESTRIAN_SPEED))
+                } else {
+                    (Point::new(x, rect.bottom()), Point::new(0, -PEDESTRIAN_SPEED))
+                }
+            }
+        };
+        Self {
+            arm: crossing.arm,
+            position,
+            step,
+            waiting: true,
+            wait_time: 0.0,
+        }
+    }
+
+    pub fn get_rect(&self) -> Rect {
+        Rect::new(self.position.x, self.position.y, PEDESTRIAN_SIZE, PEDESTRIAN_SIZE)
+    }
+
+    fn has_crossed(&self, crossing: &Crossing) -> bool {
+        !self.waiting && !self.get_rect().has_intersection(crossing.rect)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PedestrianDemand {
+    pub rate_per_minute: f32,
+}
+
+impl PedestrianDemand {
+    pub fn new() -> Self {
+        Self { rate_per_minute: 0.0 }
+    }
+
+    // Poisson arrivals, so each 60Hz tick is a Bernoulli trial with the per-tick rate
+    pub fn arrives<R: Rng>(&self, rng: &mut R) -> bool {
+        self.rate_per_minute > 0.0 && rng.gen::<f32>() < self.rate_per_minute / 3600.0
+    }
+}
+
+// Moves pedestrians along and returns the wait times of those that reached the far kerb.
+// Pedestrians have priority on a zebra, so they step out as soon as no vehicle is on it or
+// committed to leaving over it, and everyone waiting at that moment crosses together.
+pub fn update_pedestrians(
+    pedestrians: &mut Vec<Pedestrian>,
+    crossings: &[Crossing],
+    vehicles: &[Vehicle],
+    delta_time: f32
+) -> Vec<f32> {
+    for crossing in crossings {
+        let clear =
+            !crossing.has_vehicle_on(vehicles) && !crossing.has_vehicle_heading_for(vehicles);
+        for pedestrian in pedestrians.iter_mut().filter(|p| p.arm == crossing.arm) {
+            if pedestrian.waiting {
+                if clear {
+                    pedestrian.waiting = false;
+                } else {
+                    pedestrian.wait_time += delta_time;
+                }
+            }
+            if !pedestrian.waiting {
+                pedestrian.position += pedestrian.step;
+            }
+        }
+    }
+
+    let mut finished = vec![];
+    pedestrians.retain(|p| {
+        let crossing = crossings
+            .iter()
+            .find(|c| c.arm == p.arm)
+            .expect("pedestrian on a missing crossing");
+        if p.has_crossed(crossing) {
+            finished.push(p.wait_time);
+            return false;
+        }
+        true
+    });
+    finished
+}
+
+// Fastest a vehicle may go this tick without driving onto its approach's crossing while it is
+// in use, or over its stop line while the crossing on the arm it leaves by is. Vehicles past
+// the stop line are never held, so nothing stops inside the box.
+pub fn crossing_velocity_limit(
+    car: &Vehicle,
+    crossings: &[Crossing],
+    pedestrians: &[Pedestrian]
+) -> f32 {
+    let mut limit = car.velocity;
+    let distance = car.get_distance_to_stop_line();
+    if distance < 0 {
+        return limit;
+    }
+    if crossings.iter().any(|c| c.arm == car.direction && c.is_occupied(pedestrians)) {
+        limit = limit.min(distance as f32);
+    }
+    for crossing in crossings
+        .iter()
+        .filter(|c| c.arm == car.origin && c.is_occupied(pedestrians)) {
+        // A vehicle already on the crossing keeps going so it clears it
+        if car.get_rect().has_intersection(crossing.rect) {
+            continue;
+        }
+        let mut probe = car.clone();
+        let mut velocity = limit;
+        while velocity > 0.0 {
+            probe.set_velocity(velocity);
+            let future = probe.get_future_position(&car.position);
+            let future_rect = Rect::new(future.x, future.y, car.width, car.height);
+            if !future_rect.has_intersection(crossing.rect) {
+                break;
+            }
+            velocity = velocity.ceil() - 1.0;
+        }
+        limit = velocity.max(0.0);
+    }
+    limit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ layout::LaneLayout, vehicle::VehicleKind };
+
+    // A car from the north heading south, its front on its stop line at full speed
+    fn car_at_stop_line() -> Vehicle {
+        let layout = LaneLayout::new();
+        let (origin, direction) = (Direction::North, Direction::South);
+        let mut car = Vehicle::new_in_lane(origin, direction, VehicleKind::Car, &layout, 1, 0);
+        car.position.y = (WINDOW_HEIGHT as i32) / 3 - (car.height as i32);
+        car.set_velocity(VehicleKind::Car.max_speed());
+        car
+    }
+
+    #[test]
+    fn car_waits_for_the_crossing_it_leaves_by() {
+        let car = car_at_stop_line();
+        assert_eq!(car.get_distance_to_stop_line(), 0);
+        let crossings = vec![Crossing::new(Direction::North), Crossing::new(Direction::South)];
+        let mut pedestrians = vec![Pedestrian::new(&crossings[1], true)];
+        assert_eq!(crossing_velocity_limit(&car, &crossings, &pedestrians), car.velocity);
+
+        pedestrians[0].waiting = false;
+        assert_eq!(crossing_velocity_limit(&car, &crossings, &pedestrians), 0.0);
+    }
+}
@@ -1,8 +1,13 @@
-use sdl2::rect::Point;
+use crate::{
+    geometry::{ Point, Rect },
+    vehicle::{ Vehicle, VehicleKind },
+    intersection::Direction,
+    layout::Junction,
+    WINDOW_WIDTH,
+    WINDOW_HEIGHT,
+};
 
-use crate::{ vehicle::{ Vehicle, VEHICLE_HEIGHT, VEHICLE_WIDTH }, WINDOW_WIDTH, WINDOW_HEIGHT };
-
-const SAFETY_GAP: i32 = 4;
+pub const SAFETY_GAP: i32 = 4;
 
 pub fn will_vehicles_collide(vehicle_a: &Vehicle, vehicle_b: &Vehicle) -> bool {
     // Define the number of time steps
@@ -14,19 +19,15 @@ pub fn will_vehicles_collide(vehicle_a: &Vehicle, vehicle_b: &Vehicle) -> bool {
 
     // Check for collisions at each time step
     for _ in 0..num_time_steps {
+        // Calculate future positions after the current time step
         let future_position_a = vehicle_a.get_future_position(&position_a);
         let future_position_b = vehicle_b.get_future_position(&position_b);
-        // Calculate future positions after the current time step
-        let future_position_a_x = future_position_a.x;
-        let future_position_a_y = future_position_a.y;
-        let future_position_b_x = future_position_b.x;
-        let future_position_b_y = future_position_b.y;
 
         if
-            (future_position_a_x - future_position_b_x).abs() <=
-                (vehicle_a.width.max(vehicle_a.width) as i32) + SAFETY_GAP &&
-            (future_position_a_y - future_position_b_y).abs() <=
-                (vehicle_a.height.max(vehicle_a.width) as i32) + SAFETY_GAP
+            are_within_safety_gap(
+                predicted_footprint(vehicle_a, future_position_a),
+                predicted_footprint(vehicle_b, future_position_b)
+            )
         {
             // Future collision with safety gap
             return true;
@@ -39,6 +40,40 @@ pub fn will_vehicles_collide(vehicle_a: &Vehicle, vehicle_b: &Vehicle) -> bool {
     false
 }
 
+// Predictions take the kind's length and breadth the way the vehicle will be facing, so a long
+// vehicle's turn is seen swinging across the lanes beside it. On a roundabout they cannot tell
+// which way it will be facing as it goes round, so they allow for either by squaring its
+// footprint to its length.
+pub fn predicted_footprint(vehicle: &Vehicle, position: Point) -> Rect {
+    let footprint = vehicle.footprint_at(position);
+    if vehicle.junction != Junction::Roundabout {
+        return footprint;
+    }
+    let length = vehicle.width.max(vehicle.height);
+    Rect::from_center(footprint.center(), length, length)
+}
+
+// Footprints of both vehicles come closer than the safety gap. Each vehicle's own size is
+// used, so a narrow vehicle can be passed in the same lane.
+pub fn are_within_safety_gap(footprint_a: Rect, footprint_b: Rect) -> bool {
+    footprint_a.x() - SAFETY_GAP <= footprint_b.right() &&
+        footprint_b.x() - SAFETY_GAP <= footprint_a.right() &&
+        footprint_a.y() - SAFETY_GAP <= footprint_b.bottom() &&
+        footprint_b.y() - SAFETY_GAP <= footprint_a.bottom()
+}
+
+// A vehicle turning along the kerb across a bicycle beside it, a right hook when driving on the
+// right and a left hook when driving on the left. A bicycle turning the same way is hooked too,
+// as it turns later at the kerb edge and the vehicle's turn swings across it.
+pub fn is_kerb_hook(car: &Vehicle, bicycle: &Vehicle) -> bool {
+    bicycle.kind == VehicleKind::Bicycle &&
+        car.kind != VehicleKind::Bicycle &&
+        car.origin == bicycle.origin &&
+        car.direction == car.driving_side.kerb_turn(car.origin) &&
+        !bicycle.is_in_end_lane() &&
+        will_vehicles_collide(car, bicycle)
+}
+
 pub fn is_closer_to_center(point_a: Point, point_b: Point) -> bool {
     let center_x = (WINDOW_WIDTH as f32) / 2.0;
     let center_y = (WINDOW_HEIGHT as f32) / 2.0;
@@ -60,12 +95,63 @@ pub fn get_close_calls_for_vehicle(car: &mut Vehicle, other_cars: &Vec<Vehicle>)
                 .iter()
                 .filter(|cc| cc.id == other_car.id)
                 .count() < 1 &&
-            (car.position.x - other_car.position.x).abs() <=
-                (car.width.max(car.width) as i32) + SAFETY_GAP &&
-            (car.position.y - other_car.position.y).abs() <=
-                (car.height.max(car.width) as i32) + SAFETY_GAP
+            are_within_safety_gap(
+                car.get_rect(),
+                other_car.get_rect()
+            )
         {
             car.close_calls.push(other_car.clone());
         }
     }
 }
+
+pub fn get_crashes_for_vehicle(car: &mut Vehicle, other_cars: &Vec<Vehicle>) {
+    let car_rect = car.get_rect();
+    for other_car in other_cars {
+        if
+            car.id != other_car.id &&
+            !car.crashes.contains(&other_car.id) &&
+            car_rect.has_intersection(other_car.get_rect())
+        {
+            car.crashes.push(other_car.id);
+        }
+    }
+}
+
+// Distance to the car directly ahead when both travel the same way with overlapping lanes
+pub fn gap_to_vehicle_ahead(car: &Vehicle, other_car: &Vehicle) -> Option<i32> {
+    if car.id == other_car.id || car.facing != other_car.facing {
+        return None;
+    }
+    let (a, b) = (car.get_rect(), other_car.get_rect());
+    let gap = match car.facing {
+        Direction::North | Direction::South => {
+            if a.right() <= b.left() || b.right() <= a.left() {
+                return None;
+            }
+            if car.facing == Direction::South { b.top() - a.bottom() } else { a.top() - b.bottom() }
+        }
+        Direction::East | Direction::West => {
+            if a.bottom() <= b.top() || b.bottom() <= a.top() {
+                return None;
+            }
+            if car.facing == Direction::East { b.left() - a.right() } else { a.left() - b.right() }
+        }
+    };
+    if gap < 0 {
+        return None;
+    }
+    Some(gap)
+}
+
+// Fastest a vehicle may go and still keep the safety gap to the one in front of it, even if that
+// one stops dead this tick. Slowing down is immediate for every kind, so this lets a faster kind
+// follow a slower one, as a car behind a truck, without running into it.
+pub fn queue_velocity_limit(car: &Vehicle, other_cars: &[Vehicle]) -> f32 {
+    other_cars
+        .iter()
+        .filter_map(|other_car| gap_to_vehicle_ahead(car, other_car))
+        .min()
+        .map(|gap| ((gap - SAFETY_GAP) as f32).max(0.0))
+        .unwrap_or(f32::MAX)
+}
@@ -0,0 +1,69 @@
+use crate::{ vehicle::{ Vehicle, VehicleKind }, physics::gap_to_vehicle_ahead };
+
+// Most vehicles crossing as one platoon, so a long queue cannot hold a conflicting lane forever
+pub const PLATOON_SIZE: usize = 4;
+// Largest gap to the vehicle in front at which a vehicle joins its platoon. Neither 20 nor 80
+// changed throughput against distance_to_finish: up to a vehicle every 15 ticks the junction
+// keeps up with demand under both, so platoons have nothing to gain there
+const JOIN_GAP: i32 = 40;
+// Gap platoon members close up to and then hold by matching the speed in front
+const HEADWAY: i32 = 6;
+
+// Vehicles still approaching join the platoon of the vehicle just ahead in their lane when it
+// is close and the platoon has room, otherwise they lead their own. Membership is fixed once
+// a vehicle passes the stop line. Emergency vehicles always travel alone.
+pub fn form_platoons(vehicles: &mut [Vehicle]) {
+    // Front of each lane first, so a vehicle sees the platoon in front as already updated
+    let mut order: Vec<usize> = (0..vehicles.len()).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(vehicles[index].travelled));
+
+    for index in order {
+        let car = &vehicles[index];
+        if car.get_distance_to_stop_line() <= 0 {
+            continue;
+        }
+        let ahead = vehicles
+            .iter()
+            .filter(|other| other.origin == car.origin && other.lane == car.lane)
+            .filter_map(|other| gap_to_vehicle_ahead(car, other).map(|gap| (gap, other)))
+            .min_by_key(|(gap, _)| *gap);
+        let platoon = match ahead {
+            Some((gap, other)) if
+                gap <= JOIN_GAP &&
+                car.kind != VehicleKind::Emergency &&
+                other.kind != VehicleKind::Emergency &&
+                platoon_size(vehicles, other.platoon, car.id) < PLATOON_SIZE
+            => other.platoon,
+            _ => car.id,
+        };
+        vehicles[index].platoon = platoon;
+    }
+}
+
+// Members of a platoon other than the given vehicle
+fn platoon_size(vehicles: &[Vehicle], platoon: u32, without: u32) -> usize {
+    vehicles
+        .iter()
+        .filter(|v| v.platoon == platoon && v.id != without)
+        .count()
+}
+
+// Front member of the car's platoon that is still crossing, which the platoon is scheduled by
+pub fn platoon_head<'a>(car: &'a Vehicle, all_cars: &'a [Vehicle]) -> &'a Vehicle {
+    all_cars
+        .iter()
+        .filter(|c| c.platoon == car.platoon && !c.is_in_end_lane())
+        .max_by_key(|c| c.travelled)
+        .unwrap_or(car)
+}
+
+// Followers close up to HEADWAY behind the member in front and then match its speed
+pub fn platoon_velocity_limit(car: &Vehicle, all_cars: &[Vehicle]) -> f32 {
+    all_cars
+        .iter()
+        .filter(|other| other.platoon == car.platoon)
+        .filter_map(|other| gap_to_vehicle_ahead(car, other).map(|gap| (gap, other)))
+        .min_by_key(|(gap, _)| *gap)
+        .map(|(gap, ahead)| ahead.velocity + ((gap - HEADWAY).max(0) as f32))
+        .unwrap_or(f32::MAX)
+}
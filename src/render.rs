@@ -1,109 +1,109 @@
-use sdl2::{ render::{ WindowCanvas, Texture }, pixels::Color, rect::{ Rect }, ttf::Font };
+use std::collections::HashMap;
 
-use crate::{
-    intersection::{ Intersection, Direction },
+use sdl2::{
+    render::{ WindowCanvas, Texture, BlendMode },
+    pixels::Color,
+    rect::{ Rect, Point },
+    ttf::Font,
+};
+
+use smart_road::{
+    intersection::{ Intersection, Direction, Lane },
+    layout::{
+        Junction,
+        LaneLayout,
+        ROUNDABOUT_LANE,
+        RING_RADIUS,
+        approach_lane_offset,
+        exit_lane_offset,
+        ring_centre,
+    },
     WINDOW_HEIGHT,
     WINDOW_WIDTH,
-    vehicle::{ VEHICLE_WIDTH, VEHICLE_HEIGHT },
+    vehicle::{ Vehicle, VehicleKind },
     statistics::Statistics,
+    algorithm::{ Manager, priority_order, conflicting_pairs },
+    heatmap::{ HeatmapMetric, HEATMAP_CELL_SIZE, heat_colour },
+    network::Network,
 };
 
 pub const VERTICAL_LANE_WIDTH: u32 = WINDOW_WIDTH / 18;
 const VERTICAL_LANE_HEIGHT: u32 = WINDOW_HEIGHT / 3;
 pub const HORIZONTAL_LANE_HEIGHT: u32 = WINDOW_HEIGHT / 18;
 const HORIZONTAL_LANE_WIDTH: u32 = WINDOW_WIDTH / 3;
+const DEBUG_PREDICTION_STEPS: usize = 60;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenderOptions {
+    pub show_statistics: bool,
+    pub show_hud: bool,
+    pub show_debug: bool,
+    pub heatmap: Option<HeatmapMetric>,
+    pub selected_vehicle: Option<u32>,
+}
 
 fn render_intersection(
     canvas: &mut WindowCanvas,
     intersection: &Intersection,
     road_texture: &Texture
 ) -> Result<(), String> {
-    // Draw lanes
-    for (index, lane) in intersection.lanes.iter().enumerate() {
-        // Draw lane
-        canvas.set_draw_color(Color::WHITE);
-        let lane_src = Rect::new(
-            0,
-            0,
-            road_texture.query().width / 6,
-            road_texture.query().height / 2
-        );
-        let mut angle = 0.0;
-        let mut lane_rect = Rect::new(0, 0, 0, 0);
-        let mut opposite_lane_rect = Rect::new(0, 0, 0, 0);
-        let m = index % 3;
-        match lane.origin {
-            Direction::North => {
-                lane_rect.set_x(
-                    (WINDOW_WIDTH as i32) / 3 + ((VERTICAL_LANE_WIDTH * (m as u32)) as i32)
-                );
-                lane_rect.set_height(VERTICAL_LANE_HEIGHT);
-                lane_rect.set_width(VERTICAL_LANE_WIDTH);
-                opposite_lane_rect.set_x(
-                    (WINDOW_WIDTH as i32) / 3 + ((VERTICAL_LANE_WIDTH * (m as u32)) as i32)
-                );
-                opposite_lane_rect.set_y(((WINDOW_HEIGHT as i32) * 2) / 3);
-                opposite_lane_rect.set_height(VERTICAL_LANE_HEIGHT);
-                opposite_lane_rect.set_width(VERTICAL_LANE_WIDTH);
-            }
-            Direction::South => {
-                lane_rect.set_y(((WINDOW_HEIGHT as i32) * 2) / 3);
-                lane_rect.set_x(
-                    ((WINDOW_WIDTH as i32) * 2) / 3 -
-                        (VERTICAL_LANE_WIDTH as i32) -
-                        ((VERTICAL_LANE_WIDTH * (m as u32)) as i32)
-                );
-                lane_rect.set_height(VERTICAL_LANE_HEIGHT);
-                lane_rect.set_width(VERTICAL_LANE_WIDTH);
-                opposite_lane_rect.set_x(
-                    ((WINDOW_WIDTH as i32) * 2) / 3 -
-                        (VERTICAL_LANE_WIDTH as i32) -
-                        ((VERTICAL_LANE_WIDTH * (m as u32)) as i32)
-                );
-                opposite_lane_rect.set_height(VERTICAL_LANE_HEIGHT);
-                opposite_lane_rect.set_width(VERTICAL_LANE_WIDTH);
-                angle = 180.0;
-            }
-            Direction::East => {
-                lane_rect.set_x(((WINDOW_WIDTH as i32) * 2) / 3);
-                lane_rect.set_y(
-                    (WINDOW_HEIGHT as i32) / 3 + ((HORIZONTAL_LANE_HEIGHT * (m as u32)) as i32)
-                );
-                lane_rect.set_height(HORIZONTAL_LANE_HEIGHT);
-                lane_rect.set_width(HORIZONTAL_LANE_WIDTH);
-                opposite_lane_rect.set_y(
-                    (WINDOW_HEIGHT as i32) / 3 + ((HORIZONTAL_LANE_HEIGHT * (m as u32)) as i32)
-                );
-                opposite_lane_rect.set_height(HORIZONTAL_LANE_HEIGHT);
-                opposite_lane_rect.set_width(HORIZONTAL_LANE_WIDTH);
-                angle = 180.0;
-            }
-            Direction::West => {
-                lane_rect.set_y(
-                    ((WINDOW_HEIGHT as i32) * 2) / 3 -
-                        (HORIZONTAL_LANE_HEIGHT as i32) -
-                        ((HORIZONTAL_LANE_HEIGHT * (m as u32)) as i32)
-                );
-                lane_rect.set_height(HORIZONTAL_LANE_HEIGHT);
-                lane_rect.set_width(HORIZONTAL_LANE_WIDTH);
-                opposite_lane_rect.set_x(((WINDOW_WIDTH as i32) * 2) / 3);
-                opposite_lane_rect.set_y(
-                    ((WINDOW_HEIGHT as i32) * 2) / 3 -
-                        (HORIZONTAL_LANE_HEIGHT as i32) -
-                        ((HORIZONTAL_LANE_HEIGHT * (m as u32)) as i32)
-                );
-                opposite_lane_rect.set_height(HORIZONTAL_LANE_HEIGHT);
-                opposite_lane_rect.set_width(HORIZONTAL_LANE_WIDTH);
-            }
-        }
+    let lane_src = Rect::new(0, 0, road_texture.query().width / 6, road_texture.query().height / 2);
 
-        canvas.copy_ex(road_texture, lane_src, lane_rect, angle, None, false, false).unwrap();
-        canvas
-            .copy_ex(road_texture, lane_src, opposite_lane_rect, angle, None, false, false)
-            .unwrap();
-        canvas.draw_rect(lane_rect).unwrap();
-        canvas.draw_rect(opposite_lane_rect).unwrap();
+    // Draw lanes, each arm is the approach's lanes plus the lanes leaving by that side
+    let roundabout = intersection.layout.junction == Junction::Roundabout;
+    let driving_side = intersection.layout.driving_side;
+    for side in [Direction::North, Direction::South, Direction::East, Direction::West] {
+        let offsets: Vec<(i32, Direction)> = if roundabout {
+            vec![
+                (approach_lane_offset(driving_side, side, ROUNDABOUT_LANE), side.opposite()),
+                (exit_lane_offset(driving_side, side, ROUNDABOUT_LANE), side)
+            ]
+        } else {
+            (0..intersection.layout.lanes(side).len())
+                .map(|lane| (approach_lane_offset(driving_side, side, lane), side.opposite()))
+                .chain(
+                    (0..intersection.layout.exit_lanes(side)).map(|lane| {
+                        (exit_lane_offset(driving_side, side, lane), side)
+                    })
+                )
+                .collect()
+        };
+        for (offset, facing) in offsets {
+            let lane_rect = match side {
+                Direction::North => Rect::new(offset, 0, VERTICAL_LANE_WIDTH, VERTICAL_LANE_HEIGHT),
+                Direction::South =>
+                    Rect::new(
+                        offset,
+                        ((WINDOW_HEIGHT as i32) * 2) / 3,
+                        VERTICAL_LANE_WIDTH,
+                        VERTICAL_LANE_HEIGHT
+                    ),
+                Direction::East =>
+                    Rect::new(
+                        ((WINDOW_WIDTH as i32) * 2) / 3,
+                        offset,
+                        HORIZONTAL_LANE_WIDTH,
+                        HORIZONTAL_LANE_HEIGHT
+                    ),
+                Direction::West =>
+                    Rect::new(0, offset, HORIZONTAL_LANE_WIDTH, HORIZONTAL_LANE_HEIGHT),
+            };
+            let angle = match facing {
+                Direction::South | Direction::East => 0.0,
+                Direction::North | Direction::West => 180.0,
+            };
+            canvas.copy_ex(road_texture, lane_src, lane_rect, angle, None, false, false)?;
+            canvas.set_draw_color(Color::WHITE);
+            canvas.draw_rect(lane_rect)?;
+        }
+    }
+    if roundabout {
+        return render_roundabout(canvas, road_texture, &intersection.layout);
     }
+    for lane in &intersection.lanes {
+        render_lane_arrows(canvas, lane)?;
+    }
+
     let centre_square = Rect::new(
         (WINDOW_WIDTH / 3) as i32,
         (WINDOW_HEIGHT / 3) as i32,
@@ -120,45 +120,219 @@ fn render_intersection(
     Ok(())
 }
 
+// Arms run on into a ring round a central island instead of meeting in a box
+fn render_roundabout(
+    canvas: &mut WindowCanvas,
+    road_texture: &Texture,
+    layout: &LaneLayout
+) -> Result<(), String> {
+    let road_src = Rect::new(0, 0, road_texture.query().width / 7, road_texture.query().height / 3);
+    let lane_width = VERTICAL_LANE_WIDTH as i32;
+    let road_width = VERTICAL_LANE_WIDTH * 2;
+    // Each arm carries its lane in and a lane out on to the ring
+    for &side in &layout.arms {
+        let start = approach_lane_offset(layout.driving_side, side, ROUNDABOUT_LANE).min(
+            exit_lane_offset(layout.driving_side, side, ROUNDABOUT_LANE)
+        );
+        let road = match side {
+            Direction::North =>
+                Rect::new(start, (WINDOW_HEIGHT / 3) as i32, road_width, WINDOW_HEIGHT / 6),
+            Direction::South =>
+                Rect::new(start, (WINDOW_HEIGHT / 2) as i32, road_width, WINDOW_HEIGHT / 6),
+            Direction::East =>
+                Rect::new((WINDOW_WIDTH / 2) as i32, start, WINDOW_WIDTH / 6, road_width),
+            Direction::West =>
+                Rect::new((WINDOW_WIDTH / 3) as i32, start, WINDOW_WIDTH / 6, road_width),
+        };
+        canvas.copy(road_texture, road_src, road)?;
+    }
+
+    let (centre_x, centre_y) = ring_centre();
+    let centre = Point::new(centre_x as i32, centre_y as i32);
+    let radius = RING_RADIUS as i32;
+    canvas.set_draw_color(Color::RGB(70, 70, 70));
+    fill_circle(canvas, centre, radius + lane_width / 2)?;
+    canvas.set_draw_color(Color { r: 0, g: 100, b: 0, a: 1 });
+    fill_circle(canvas, centre, radius - lane_width / 2)?;
+    canvas.set_draw_color(Color::WHITE);
+    draw_circle(canvas, centre, radius - lane_width / 2)?;
+    Ok(())
+}
+
+fn fill_circle(canvas: &mut WindowCanvas, centre: Point, radius: i32) -> Result<(), String> {
+    for dy in -radius..=radius {
+        let half = ((radius * radius - dy * dy) as f32).sqrt() as i32;
+        canvas.draw_line(
+            Point::new(centre.x - half, centre.y + dy),
+            Point::new(centre.x + half, centre.y + dy)
+        )?;
+    }
+    Ok(())
+}
+
+fn draw_circle(canvas: &mut WindowCanvas, centre: Point, radius: i32) -> Result<(), String> {
+    let points: Vec<Point> = (0..=72)
+        .map(|step| {
+            let angle = ((step as f32) * 5.0).to_radians();
+            Point::new(
+                centre.x + ((radius as f32) * angle.cos()) as i32,
+                centre.y + ((radius as f32) * angle.sin()) as i32
+            )
+        })
+        .collect();
+    canvas.draw_lines(&points[..])
+}
+
+// Road markings before the stop line showing the movements a lane allows
+fn render_lane_arrows(canvas: &mut WindowCanvas, lane: &Lane) -> Result<(), String> {
+    let (forward_x, forward_y) = match lane.origin.opposite() {
+        Direction::North => (0, -1),
+        Direction::South => (0, 1),
+        Direction::East => (1, 0),
+        Direction::West => (-1, 0),
+    };
+    // Driver's left, with y growing down the screen
+    let (left_x, left_y) = (forward_y, -forward_x);
+    let across =
+        approach_lane_offset(lane.driving_side, lane.origin, lane.index) +
+        (VERTICAL_LANE_WIDTH as i32) / 2;
+    let before_stop_line = 24;
+    let centre = match lane.origin {
+        Direction::North => Point::new(across, (WINDOW_HEIGHT as i32) / 3 - before_stop_line),
+        Direction::South =>
+            Point::new(across, ((WINDOW_HEIGHT as i32) * 2) / 3 + before_stop_line),
+        Direction::East => Point::new(((WINDOW_WIDTH as i32) * 2) / 3 + before_stop_line, across),
+        Direction::West => Point::new((WINDOW_WIDTH as i32) / 3 - before_stop_line, across),
+    };
+    let at = |along: i32, left: i32| {
+        Point::new(
+            centre.x + forward_x * along + left_x * left,
+            centre.y + forward_y * along + left_y * left
+        )
+    };
+
+    canvas.set_draw_color(Color::WHITE);
+    canvas.draw_line(at(-10, 0), at(2, 0))?;
+    if lane.lane_use.straight {
+        canvas.draw_lines(&[at(6, -3), at(10, 0), at(6, 3)][..])?;
+        canvas.draw_line(at(2, 0), at(10, 0))?;
+    }
+    if lane.lane_use.left {
+        canvas.draw_lines(&[at(5, 5), at(2, 8), at(-1, 5)][..])?;
+        canvas.draw_line(at(2, 0), at(2, 8))?;
+    }
+    if lane.lane_use.right {
+        canvas.draw_lines(&[at(5, -5), at(2, -8), at(-1, -5)][..])?;
+        canvas.draw_line(at(2, 0), at(2, -8))?;
+    }
+    Ok(())
+}
+
+fn render_crossings(canvas: &mut WindowCanvas, intersection: &Intersection) -> Result<(), String> {
+    let stripe = 4;
+    for crossing in intersection.crossings.iter().filter(|c| intersection.layout.has_arm(c.arm)) {
+        let rect = crossing.rect;
+        canvas.set_draw_color(Color::WHITE);
+        match crossing.arm {
+            Direction::North | Direction::South => {
+                for x in (rect.left()..rect.right()).step_by(stripe * 2) {
+                    canvas.fill_rect(Rect::new(x, rect.top(), stripe as u32, rect.height()))?;
+                }
+            }
+            Direction::East | Direction::West => {
+                for y in (rect.top()..rect.bottom()).step_by(stripe * 2) {
+                    canvas.fill_rect(Rect::new(rect.left(), y, rect.width(), stripe as u32))?;
+                }
+            }
+        }
+    }
+
+    for pedestrian in &intersection.pedestrians {
+        if pedestrian.waiting {
+            canvas.set_draw_color(Color::RGB(255, 165, 0));
+        } else {
+            canvas.set_draw_color(Color::CYAN);
+        }
+        canvas.fill_rect(Rect::from(pedestrian.get_rect()))?;
+    }
+    Ok(())
+}
+
+fn render_heatmap(
+    canvas: &mut WindowCanvas,
+    intersection: &Intersection,
+    metric: HeatmapMetric
+) -> Result<(), String> {
+    let heatmap = &intersection.heatmap;
+    let values = heatmap.normalised(metric);
+
+    canvas.set_blend_mode(BlendMode::Blend);
+    for (index, value) in values.iter().enumerate() {
+        if *value <= 0.0 {
+            continue;
+        }
+        let (r, g, b, a) = heat_colour(*value);
+        let column = (index as u32) % heatmap.columns;
+        let row = (index as u32) / heatmap.columns;
+        canvas.set_draw_color(Color::RGBA(r, g, b, a));
+        canvas.fill_rect(
+            Rect::new(
+                (column * HEATMAP_CELL_SIZE) as i32,
+                (row * HEATMAP_CELL_SIZE) as i32,
+                HEATMAP_CELL_SIZE,
+                HEATMAP_CELL_SIZE
+            )
+        )?;
+    }
+    canvas.set_blend_mode(BlendMode::None);
+    Ok(())
+}
+
 fn render_cars(
     canvas: &mut WindowCanvas,
     intersection: &Intersection,
-    car_texture: &Texture
+    car_texture: &Texture,
+    vehicle_textures: &HashMap<VehicleKind, Texture>
 ) -> Result<(), String> {
     canvas.set_draw_color(Color::RED);
     for car in &intersection.vehicles {
         if car.colliding {
             canvas.set_draw_color(Color::GREEN);
+        } else if car.kind == VehicleKind::Emergency {
+            canvas.set_draw_color(Color::BLUE);
         } else {
             canvas.set_draw_color(Color::RED);
         }
         let mut angle = 0.0;
+        let length = car.kind.length();
+        let breadth = car.kind.breadth();
         let actual_rect = Rect::new(car.position.x, car.position.y, car.width, car.height);
-        let mut screen_rect = Rect::new(
-            car.position.x,
-            car.position.y,
-            VEHICLE_WIDTH - 10,
-            VEHICLE_HEIGHT - 10
-        );
-        let src = Rect::new(
-            0,
-            ((car_texture.query().height * 3) / 5) as i32,
-            car_texture.query().width,
-            (car_texture.query().height * 2) / 5
-        );
+        let mut screen_rect = Rect::new(car.position.x, car.position.y, length, breadth);
+
+        // Sprites for the other kinds are whole images, the car sits in the lower part of its sheet
+        let (texture, src) = match vehicle_textures.get(&car.kind) {
+            Some(texture) =>
+                (texture, Rect::new(0, 0, texture.query().width, texture.query().height)),
+            None =>
+                (
+                    car_texture,
+                    Rect::new(
+                        0,
+                        ((car_texture.query().height * 3) / 5) as i32,
+                        car_texture.query().width,
+                        (car_texture.query().height * 2) / 5
+                    ),
+                ),
+        };
         match car.facing {
             Direction::North => {
-                screen_rect.set_x(
-                    car.position.x - ((VEHICLE_WIDTH - 10).abs_diff(VEHICLE_HEIGHT - 10) as i32) / 2
-                );
-                screen_rect.set_y(car.position.y + 5);
+                screen_rect.set_x(car.position.x - (length.abs_diff(breadth) as i32) / 2);
+                screen_rect.set_y(car.position.y + (length.abs_diff(breadth) as i32) / 2);
                 angle = 90.0;
             }
             Direction::South => {
-                screen_rect.set_x(
-                    car.position.x - ((VEHICLE_WIDTH - 10).abs_diff(VEHICLE_HEIGHT - 10) as i32) / 2
-                );
-                screen_rect.set_y(car.position.y + 5);
+                screen_rect.set_x(car.position.x - (length.abs_diff(breadth) as i32) / 2);
+                screen_rect.set_y(car.position.y + (length.abs_diff(breadth) as i32) / 2);
                 angle = 270.0;
             }
             Direction::East => {
@@ -169,17 +343,17 @@ fn render_cars(
         let perim = Rect::from_center(
             screen_rect.center(),
             if car.facing == Direction::North || car.facing == Direction::South {
-                VEHICLE_HEIGHT - 10
+                breadth
             } else {
-                VEHICLE_WIDTH - 10
+                length
             },
             if car.facing == Direction::North || car.facing == Direction::South {
-                VEHICLE_WIDTH - 10
+                length
             } else {
-                VEHICLE_HEIGHT - 10
+                breadth
             }
         );
-        canvas.copy_ex(car_texture, src, screen_rect, angle, None, false, false)?;
+        canvas.copy_ex(texture, src, screen_rect, angle, None, false, false)?;
         canvas.draw_rect(actual_rect).unwrap();
         canvas.draw_rect(perim).unwrap();
     }
@@ -189,12 +363,14 @@ fn render_cars(
 fn render_statistics(
     canvas: &mut WindowCanvas,
     font: &Font,
-    stats: &Statistics
+    stats: &Statistics,
+    title: &str,
+    x: i32
 ) -> Result<(), String> {
     // Create a smaller window (viewport) within the main window
     let viewport_width = 200;
-    let viewport_height = 300;
-    let viewport_rect = Rect::new(200, 150, viewport_width, viewport_height);
+    let viewport_height = 330;
+    let viewport_rect = Rect::new(x, 150, viewport_width, viewport_height);
 
     // Set the viewport for the smaller window
     canvas.set_viewport(viewport_rect);
@@ -207,13 +383,25 @@ fn render_statistics(
     let surface = font
         .render(
             format!(
-                "Statistics\nNumber of vehicles: {}\nMax Velocity: {}\nMin Velocity: {}\nMax Time: {:.2} seconds\nMin Time: {:.2} seconds\nClose Calls: {}",
+                "{}\nNumber of vehicles: {}\nMax Velocity: {}\nMin Velocity: {}\nMax Time: {:.2} seconds\nMin Time: {:.2} seconds\nClose Calls: {}\nCrashes: {}\nEmergency Vehicles: {}\nEmergency Avg Delay: {:.2} seconds\nEmergency Max Delay: {:.2} seconds\nPedestrians: {}\nPedestrian Avg Wait: {:.2} seconds\nPedestrian Max Wait: {:.2} seconds\nMax Wait: {:.2} seconds\nFairness (Jain): {:.2}\nAuction Revenue: {:.1} credits\nWelfare: {:.1} credits",
+                title,
                 stats.num_vehicles,
                 stats.max_velocity,
                 stats.min_velocity,
                 stats.max_time / 1000.0,
                 stats.min_time / 1000.0,
-                stats.close_calls / 2
+                stats.close_calls / 2,
+                stats.crashes / 2,
+                stats.emergency_vehicles,
+                stats.average_emergency_delay() / 1000.0,
+                stats.emergency_max_delay / 1000.0,
+                stats.pedestrians,
+                stats.average_pedestrian_wait() / 1000.0,
+                stats.pedestrian_max_wait / 1000.0,
+                stats.max_wait / 1000.0,
+                stats.fairness_index(),
+                stats.auction_revenue,
+                stats.welfare()
             ).as_str()
         )
         .blended_wrapped(Color::RGB(0, 0, 0), viewport_width)
@@ -235,24 +423,299 @@ fn render_statistics(
     Ok(())
 }
 
-pub fn render(
+fn render_hud(canvas: &mut WindowCanvas, font: &Font, network: &Network) -> Result<(), String> {
+    let hud_width = 170;
+    let intersection = network.focused();
+    let mut text = format!(
+        "Junction: {} with {} arms, driving on the {}\n",
+        intersection.layout.junction.name(),
+        intersection.layout.arms.len(),
+        intersection.layout.driving_side.name()
+    );
+    text += &format!("Manager: {}\n", intersection.manager.name());
+    if intersection.manager == Manager::Optimised {
+        text += &format!("Planned delay: {:.0} ticks\n", intersection.planned_delay);
+    }
+    if intersection.manager == Manager::Auction {
+        text += &format!(
+            "Planned delay: {:.0} ticks\nAuction revenue: {:.1} credits\n",
+            intersection.planned_delay,
+            intersection.stats.auction_revenue
+        );
+    }
+    text += &format!(
+        "Lane movements: {}, conflicting pairs: {}\n",
+        intersection.conflicts.movements.len(),
+        intersection.conflicts.conflict_count()
+    );
+    if network.intersections.len() > 1 {
+        text += &format!(
+            "Intersection {},{} of {}x{}\nLeft network: {}\n",
+            network.focused / network.columns,
+            network.focused % network.columns,
+            network.rows,
+            network.columns,
+            network.vehicles_left_network
+        );
+    }
+    text.push_str(
+        &format!(
+            "Tick: {}\nVehicles in box: {}\nQueues N/S/E/W: {}/{}/{}/{}\nThroughput: {:.1} per minute\nClose Calls: {}\nCrashes: {}\nEmergency Avg Delay: {:.2} seconds\nPedestrians waiting: {}\nPedestrian Avg Wait: {:.2} seconds\nFairness: {:.2}, max wait {:.1} seconds",
+            intersection.tick,
+            intersection.vehicles_in_box(),
+            intersection.queue_length(Direction::North),
+            intersection.queue_length(Direction::South),
+            intersection.queue_length(Direction::East),
+            intersection.queue_length(Direction::West),
+            intersection.stats.throughput_per_minute(intersection.tick),
+            intersection.live_close_calls(),
+            intersection.live_crashes(),
+            intersection.stats.average_emergency_delay() / 1000.0,
+            intersection.pedestrians
+                .iter()
+                .filter(|p| p.waiting)
+                .count(),
+            intersection.stats.average_pedestrian_wait() / 1000.0,
+            intersection.stats.fairness_index(),
+            intersection.stats.max_wait / 1000.0
+        )
+    );
+    let surface = font
+        .render(&text)
+        .blended_wrapped(Color::WHITE, hud_width)
+        .map_err(|e| e.to_string())?;
+
+    let texture_creator = canvas.texture_creator();
+    let texture = texture_creator
+        .create_texture_from_surface(&surface)
+        .map_err(|e| e.to_string())?;
+    let texture_query = texture.query();
+
+    // Translucent backing so the HUD stays readable over the road
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    canvas.fill_rect(Rect::new(0, 0, texture_query.width + 10, texture_query.height + 10))?;
+    canvas.set_blend_mode(BlendMode::None);
+
+    canvas.copy(&texture, None, Rect::new(5, 5, texture_query.width, texture_query.height))?;
+    Ok(())
+}
+
+fn render_debug_overlay(
+    canvas: &mut WindowCanvas,
+    font: &Font,
+    intersection: &Intersection
+) -> Result<(), String> {
+    let centre = |x: i32, y: i32, w: u32, h: u32| {
+        Point::new(x + (w as i32) / 2, y + (h as i32) / 2)
+    };
+
+    // Predicted future positions, sampled so the dots stay readable
+    canvas.set_draw_color(Color::YELLOW);
+    for car in &intersection.vehicles {
+        let path = car.get_predicted_path(DEBUG_PREDICTION_STEPS);
+        for (step, position) in path.iter().enumerate() {
+            if step % 5 == 0 {
+                let point = centre(position.x, position.y, car.width, car.height);
+                canvas.fill_rect(Rect::from_center(point, 3, 3))?;
+            }
+        }
+    }
+
+    // Pairs the algorithm is resolving, drawn from the yielding car to the one it yields to
+    canvas.set_draw_color(Color::MAGENTA);
+    let pairs = conflicting_pairs(
+        &intersection.vehicles,
+        &intersection.conflicts,
+        intersection.manager,
+        intersection.max_wait
+    );
+    for (car, other_car) in pairs {
+        canvas.draw_line(
+            centre(car.position.x, car.position.y, car.width, car.height),
+            centre(other_car.position.x, other_car.position.y, other_car.width, other_car.height)
+        )?;
+    }
+
+    // Priority order used by determine_velocity, 1 being served first
+    let texture_creator = canvas.texture_creator();
+    let ordered = priority_order(
+        &intersection.vehicles,
+        intersection.manager,
+        intersection.max_wait
+    );
+    for (index, car) in ordered.iter().enumerate() {
+        let surface = font
+            .render(&(index + 1).to_string())
+            .blended(Color::YELLOW)
+            .map_err(|e| e.to_string())?;
+        let texture = texture_creator
+            .create_texture_from_surface(&surface)
+            .map_err(|e| e.to_string())?;
+        let texture_query = texture.query();
+        canvas.copy(
+            &texture,
+            None,
+            Rect::new(
+                car.position.x,
+                car.position.y - (texture_query.height as i32),
+                texture_query.width,
+                texture_query.height
+            )
+        )?;
+    }
+    Ok(())
+}
+
+fn render_vehicle_info(
+    canvas: &mut WindowCanvas,
+    font: &Font,
+    car: &Vehicle
+) -> Result<(), String> {
+    let panel_width = 170;
+
+    let surface = font
+        .render(
+            format!(
+                "Vehicle {}\nType: {}\nOrigin: {:?}\nDirection: {:?}\nLane: {}\nPlatoon: {}\nSlot: {}\nBudget: {:.1} credits\nValue of time: {:.1} per second\nVelocity: {}\nTime: {:.2} seconds\nDistance to finish: {}\nClose Calls: {}\nRoute ahead: {:?}",
+                car.id,
+                car.kind.name(),
+                car.origin,
+                car.direction,
+                car.lane,
+                car.platoon,
+                car.slot.map_or("none".to_string(), |slot| format!("tick {}", slot)),
+                car.budget,
+                car.value_of_time,
+                car.velocity,
+                car.time / 1000.0,
+                car.get_distance_to_finish(),
+                car.close_calls.len(),
+                car.route
+            ).as_str()
+        )
+        .blended_wrapped(Color::WHITE, panel_width)
+        .map_err(|e| e.to_string())?;
+
+    let texture_creator = canvas.texture_creator();
+    let texture = texture_creator
+        .create_texture_from_surface(&surface)
+        .map_err(|e| e.to_string())?;
+    let texture_query = texture.query();
+    let panel_x = (WINDOW_WIDTH - texture_query.width - 10) as i32;
+
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    canvas.fill_rect(Rect::new(panel_x, 0, texture_query.width + 10, texture_query.height + 10))?;
+    canvas.set_blend_mode(BlendMode::None);
+
+    canvas.copy(
+        &texture,
+        None,
+        Rect::new(panel_x + 5, 5, texture_query.width, texture_query.height)
+    )?;
+    Ok(())
+}
+
+// Draws one intersection in its own coordinate space, which the caller maps onto the window
+fn render_scene(
     canvas: &mut WindowCanvas,
     intersection: &Intersection,
     car_texture: &Texture,
+    vehicle_textures: &HashMap<VehicleKind, Texture>,
+    road_texture: &Texture,
+    font: &Font,
+    options: RenderOptions
+) -> Result<(), String> {
+    render_intersection(canvas, intersection, road_texture)?;
+    render_crossings(canvas, intersection)?;
+    if let Some(metric) = options.heatmap {
+        render_heatmap(canvas, intersection, metric)?;
+    }
+    render_cars(canvas, intersection, car_texture, vehicle_textures)?;
+
+    if options.show_debug {
+        render_debug_overlay(canvas, font, intersection)?;
+    }
+
+    // Outline the inspected vehicle so it can be followed
+    let selected = options.selected_vehicle.and_then(|id| {
+        intersection.vehicles.iter().find(|v| v.id == id)
+    });
+    if let Some(car) = selected {
+        canvas.set_draw_color(Color::CYAN);
+        canvas.draw_rect(car.get_rect().into())?;
+    }
+    Ok(())
+}
+
+pub fn render(
+    canvas: &mut WindowCanvas,
+    network: &Network,
+    car_texture: &Texture,
+    vehicle_textures: &HashMap<VehicleKind, Texture>,
     road_texture: &Texture,
     font: &Font,
-    show_statistics: bool
+    options: RenderOptions
 ) -> Result<(), String> {
     canvas.set_draw_color(Color { r: 0, g: 100, b: 0, a: 1 });
     canvas.clear();
 
-    render_intersection(canvas, intersection, road_texture)?;
-    render_cars(canvas, intersection, car_texture)?;
+    // Every intersection is drawn at full size and scaled down into its tile of the grid
+    let (columns, rows) = (network.columns as u32, network.rows as u32);
+    canvas.set_scale(1.0 / (columns as f32), 1.0 / (rows as f32))?;
+    for (index, intersection) in network.intersections.iter().enumerate() {
+        let (row, column) = ((index as u32) / columns, (index as u32) % columns);
+        canvas.set_viewport(
+            Rect::new(
+                (column * WINDOW_WIDTH) as i32,
+                (row * WINDOW_HEIGHT) as i32,
+                WINDOW_WIDTH,
+                WINDOW_HEIGHT
+            )
+        );
+        render_scene(
+            canvas,
+            intersection,
+            car_texture,
+            vehicle_textures,
+            road_texture,
+            font,
+            options
+        )?;
+        if index == network.focused && network.intersections.len() > 1 {
+            canvas.set_draw_color(Color::YELLOW);
+            canvas.draw_rect(Rect::new(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT))?;
+        }
+    }
+    canvas.set_scale(1.0, 1.0)?;
+    canvas.set_viewport(Rect::new(0, 0, WINDOW_WIDTH, WINDOW_HEIGHT));
 
-    if show_statistics {
-        render_statistics(canvas, font, &intersection.stats)?;
+    let intersection = network.focused();
+
+    if options.show_hud {
+        render_hud(canvas, font, network)?;
+    }
+
+    let selected = options.selected_vehicle.and_then(|id| {
+        network.intersections
+            .iter()
+            .flat_map(|i| i.vehicles.iter())
+            .find(|v| v.id == id)
+    });
+    if let Some(car) = selected {
+        render_vehicle_info(canvas, font, car)?;
+    }
+
+    if options.show_statistics && network.mirrored {
+        // Junctions given the same arrivals are compared side by side
+        for (index, compared) in network.intersections.iter().enumerate() {
+            let title = format!("Statistics ({})", compared.layout.junction.name());
+            render_statistics(canvas, font, &compared.stats, &title, 90 + 220 * (index as i32))?;
+        }
+    } else if options.show_statistics {
+        render_statistics(canvas, font, &intersection.stats, "Statistics", 200)?;
     }
 
-    canvas.present();
     Ok(())
 }
@@ -0,0 +1,338 @@
+use std::{ cmp::Reverse, collections::{ BinaryHeap, VecDeque } };
+
+use crate::{
+    intersection::{ Direction, Intersection },
+    vehicle::{ Vehicle, VehicleKind },
+    layout::LaneLayout,
+    WINDOW_WIDTH,
+};
+
+const SIDES: [Direction; 4] = [Direction::North, Direction::South, Direction::East, Direction::West];
+// Ticks each queued vehicle adds to the wait at an approach, about one car length at cruise
+const QUEUED_VEHICLE_TICKS: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteCost {
+    // Fewest pixels driven
+    Distance,
+    // Fewest ticks, counting the queues currently waiting at each approach
+    TravelTime,
+}
+
+impl RouteCost {
+    pub fn from_name(name: &str) -> Option<RouteCost> {
+        match name {
+            "distance" => Some(RouteCost::Distance),
+            "travel_time" => Some(RouteCost::TravelTime),
+            _ => None,
+        }
+    }
+}
+
+// Node of the road graph, the approach into an intersection from one of its sides
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Approach {
+    pub intersection: usize,
+    pub side: Direction,
+}
+
+// Movement through an intersection and on to the next approach, or off the network
+#[derive(Debug, Clone, Copy)]
+pub struct Link {
+    pub exit: Direction,
+    pub to: Option<usize>,
+    // Pixels from entering the intersection's window to leaving it
+    pub length: u32,
+}
+
+pub struct Route {
+    // Exit to take at each intersection in turn
+    pub exits: VecDeque<Direction>,
+    // Intersection and side the route leaves the network by
+    pub leaves_at: (usize, Direction),
+}
+
+pub struct RoadGraph {
+    pub approaches: Vec<Approach>,
+    // Links leaving each approach, indexed like approaches
+    pub links: Vec<Vec<Link>>,
+}
+
+impl RoadGraph {
+    // Builds the graph over the intersections' layouts, neighbour gives the intersection across
+    // a side
+    pub fn new(
+        intersections: &[Intersection],
+        neighbour: impl Fn(usize, Direction) -> Option<usize>
+    ) -> Self {
+        let approaches: Vec<Approach> = (0..intersections.len())
+            .flat_map(|intersection| SIDES.map(|side| Approach { intersection, side }))
+            .collect();
+
+        let links = approaches
+            .iter()
+            .map(|approach| {
+                SIDES.iter()
+                    .filter(|&&exit| exit != approach.side)
+                    .map(|&exit| Link {
+                        exit,
+                        to: neighbour(approach.intersection, exit).map(|next| {
+                            approach_index(next, exit.opposite())
+                        }),
+                        length: movement_length(
+                            &intersections[approach.intersection].layout,
+                            approach.side,
+                            exit
+                        ),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self { approaches, links }
+    }
+
+    fn link_cost(
+        &self,
+        from: usize,
+        link: &Link,
+        kind: VehicleKind,
+        cost: RouteCost,
+        intersections: &[Intersection]
+    ) -> u32 {
+        match cost {
+            RouteCost::Distance => link.length,
+            RouteCost::TravelTime => {
+                let approach = self.approaches[from];
+                let queued = intersections[approach.intersection].queue_length(approach.side);
+                ((link.length as f32) / kind.max_speed()) as u32 +
+                    (queued as u32) * QUEUED_VEHICLE_TICKS
+            }
+        }
+    }
+
+    // Dijkstra from an approach over the movements this kind may make, giving the cheapest
+    // route to every exit off the network that can be reached
+    pub fn routes_from(
+        &self,
+        start: usize,
+        kind: VehicleKind,
+        cost: RouteCost,
+        intersections: &[Intersection]
+    ) -> Vec<Route> {
+        let mut best = vec![u32::MAX; self.approaches.len()];
+        // Approach and link that reached each approach most cheaply
+        let mut previous: Vec<Option<(usize, Direction)>> = vec![None; self.approaches.len()];
+        let mut heap = BinaryHeap::new();
+        best[start] = 0;
+        heap.push(Reverse((0, start)));
+
+        while let Some(Reverse((total, from))) = heap.pop() {
+            if total > best[from] {
+                continue;
+            }
+            let approach = self.approaches[from];
+            let layout = &intersections[approach.intersection].layout;
+            for link in self.links[from]
+                .iter()
+                .filter(|l| is_open(layout, approach.side, l, kind)) {
+                if let Some(to) = link.to {
+                    let next = total + self.link_cost(from, link, kind, cost, intersections);
+                    if next < best[to] {
+                        best[to] = next;
+                        previous[to] = Some((from, link.exit));
+                        heap.push(Reverse((next, to)));
+                    }
+                }
+            }
+        }
+
+        // An exit can be left by from several approaches, only the cheapest is kept
+        let mut routes: Vec<(u32, Route)> = vec![];
+        for (from, links) in self.links.iter().enumerate() {
+            if best[from] == u32::MAX {
+                continue;
+            }
+            let approach = self.approaches[from];
+            let layout = &intersections[approach.intersection].layout;
+            for link in links
+                .iter()
+                .filter(|l| l.to.is_none() && is_open(layout, approach.side, l, kind)) {
+                let total = best[from] + self.link_cost(from, link, kind, cost, intersections);
+                let leaves_at = (approach.intersection, link.exit);
+                if routes.iter().any(|(t, r)| r.leaves_at == leaves_at && *t <= total) {
+                    continue;
+                }
+                routes.retain(|(_, r)| r.leaves_at != leaves_at);
+                let mut exits = VecDeque::from([link.exit]);
+                let mut at = from;
+                while let Some((before, exit)) = previous[at] {
+                    exits.push_front(exit);
+                    at = before;
+                }
+                routes.push((total, Route { exits, leaves_at }));
+            }
+        }
+        routes
+            .into_iter()
+            .map(|(_, route)| route)
+            .collect()
+    }
+}
+
+// A link can be driven when both its arms exist and this kind may make the movement
+fn is_open(layout: &LaneLayout, side: Direction, link: &Link, kind: VehicleKind) -> bool {
+    layout.has_arm(side) &&
+        layout.has_arm(link.exit) &&
+        kind.can_make(side, link.exit, layout.driving_side)
+}
+
+// Approaches are stored four to an intersection in the order of SIDES
+pub fn approach_index(intersection: usize, side: Direction) -> usize {
+    intersection * SIDES.len() + SIDES.iter().position(|&s| s == side).unwrap_or(0)
+}
+
+// Length of a movement across one intersection's window, from the kerbmost lane allowed to make
+// it to its exit. Movements to or from a missing arm are never driven and have no length.
+fn movement_length(layout: &LaneLayout, origin: Direction, direction: Direction) -> u32 {
+    let lane = match layout.legal_lanes(origin, direction).first() {
+        Some(&lane) => lane,
+        None => {
+            return 0;
+        }
+    };
+    let vehicle = Vehicle::new_in_lane(origin, direction, VehicleKind::Car, layout, lane, 0);
+    vehicle.get_distance_to_finish() + WINDOW_WIDTH / 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Intersections 0 and 1 across the top row, 2 and 3 below them
+    fn grid() -> (Vec<Intersection>, RoadGraph) {
+        let intersections: Vec<Intersection> = (0..4).map(|_| Intersection::new()).collect();
+        let graph = RoadGraph::new(&intersections, grid_neighbour);
+        (intersections, graph)
+    }
+
+    fn grid_neighbour(index: usize, side: Direction) -> Option<usize> {
+        let (row, column) = (index / 2, index % 2);
+        match side {
+            Direction::North if row > 0 => Some(index - 2),
+            Direction::South if row < 1 => Some(index + 2),
+            Direction::West if column > 0 => Some(index - 1),
+            Direction::East if column < 1 => Some(index + 1),
+            _ => None,
+        }
+    }
+
+    fn route_leaving_at(
+        routes: &[Route],
+        leaves_at: (usize, Direction)
+    ) -> Option<Vec<Direction>> {
+        routes
+            .iter()
+            .find(|route| route.leaves_at == leaves_at)
+            .map(|route| route.exits.iter().copied().collect())
+    }
+
+    #[test]
+    fn shortest_route_goes_straight_through() {
+        let (intersections, graph) = grid();
+        let start = approach_index(0, Direction::West);
+        let routes = graph.routes_from(
+            start,
+            VehicleKind::Car,
+            RouteCost::Distance,
+            &intersections
+        );
+        assert_eq!(
+            route_leaving_at(&routes, (1, Direction::East)),
+            Some(vec![Direction::East, Direction::East])
+        );
+        // One route to each of the grid's eight outer sides, going round to the one entered by
+        assert_eq!(routes.len(), 8);
+    }
+
+    #[test]
+    fn travel_time_avoids_the_queued_approach() {
+        // Via 1 or via 2 to leave 3 eastwards is the same straight on, right and left turn
+        for (queued_at, side, expected) in [
+            (1, Direction::West, vec![Direction::South, Direction::East, Direction::East]),
+            (2, Direction::North, vec![Direction::East, Direction::South, Direction::East]),
+        ] {
+            let (mut intersections, graph) = grid();
+            let layout = LaneLayout::new();
+            for id in 0..3 {
+                let queued = Vehicle::new_in_lane(
+                    side,
+                    side.opposite(),
+                    VehicleKind::Car,
+                    &layout,
+                    1,
+                    id
+                );
+                intersections[queued_at].queued_vehicles.push_back(queued);
+            }
+            let start = approach_index(0, Direction::West);
+            let routes = graph.routes_from(
+                start,
+                VehicleKind::Car,
+                RouteCost::TravelTime,
+                &intersections
+            );
+            assert_eq!(route_leaving_at(&routes, (3, Direction::East)), Some(expected));
+        }
+    }
+
+    #[test]
+    fn unreachable_exits_have_no_route() {
+        let mut intersection = Intersection::new();
+        let mut layout = LaneLayout::new();
+        layout.arms.retain(|&side| side != Direction::North);
+        intersection.set_layout(layout);
+        let intersections = vec![intersection];
+        let graph = RoadGraph::new(&intersections, |_, _| None);
+        let start = approach_index(0, Direction::West);
+
+        // No road leaves north
+        let routes = graph.routes_from(
+            start,
+            VehicleKind::Car,
+            RouteCost::Distance,
+            &intersections
+        );
+        let mut exits: Vec<Direction> = routes.iter().map(|route| route.leaves_at.1).collect();
+        exits.sort_by_key(|&side| approach_index(0, side));
+        assert_eq!(exits, vec![Direction::South, Direction::East]);
+
+        // A bicycle from the east cannot turn left across the traffic to the south
+        let start = approach_index(0, Direction::East);
+        let routes = graph.routes_from(
+            start,
+            VehicleKind::Car,
+            RouteCost::Distance,
+            &intersections
+        );
+        assert!(route_leaving_at(&routes, (0, Direction::South)).is_some());
+        let routes = graph.routes_from(
+            start,
+            VehicleKind::Bicycle,
+            RouteCost::Distance,
+            &intersections
+        );
+        let exits: Vec<Direction> = routes.iter().map(|route| route.leaves_at.1).collect();
+        assert_eq!(exits, vec![Direction::West]);
+
+        // An approach with no arm reaches nothing
+        let start = approach_index(0, Direction::North);
+        let routes = graph.routes_from(
+            start,
+            VehicleKind::Car,
+            RouteCost::Distance,
+            &intersections
+        );
+        assert!(routes.is_empty());
+    }
+}
@@ -0,0 +1,457 @@
+use std::collections::HashMap;
+
+use crate::{
+    geometry::Rect,
+    algorithm::is_overdue,
+    conflict::{ ConflictTable, ConflictZone, remaining_path_segments, conflict_zone },
+    vehicle::{ Vehicle, VehicleKind },
+    physics::{ SAFETY_GAP, are_within_safety_gap },
+    intersection::Direction,
+    layout::{ approach_lane_offset, LANE_WIDTH },
+    WINDOW_WIDTH,
+    WINDOW_HEIGHT,
+};
+
+// Ticks between replans, often enough that new arrivals get a slot well before the stop line
+pub const REPLAN_INTERVAL: u64 = 15;
+// Approaching vehicles nearest the stop line that are planned, the rest wait for a later plan
+const PLANNED_VEHICLES: usize = 10;
+// Partial orders the search expands before settling for the best complete one found
+const MAX_NODES: usize = 20000;
+// Vehicles held at the stop line are assumed to pull away from it at a crawl
+const ENTRY_VELOCITY: f32 = 1.0;
+
+// Ticks to drive a distance from a velocity, accelerating up to cruising speed on the way
+fn travel_ticks(distance: f32, velocity: f32, kind: VehicleKind) -> f32 {
+    let (cruise, acceleration) = (kind.max_speed(), kind.acceleration());
+    if distance <= 0.0 {
+        return distance / cruise;
+    }
+    let velocity = velocity.clamp(ENTRY_VELOCITY, cruise);
+    let ramp_ticks = (cruise - velocity) / acceleration;
+    let ramp_distance = ((velocity + cruise) / 2.0) * ramp_ticks;
+    if distance <= ramp_distance {
+        ((velocity * velocity + 2.0 * acceleration * distance).sqrt() - velocity) / acceleration
+    } else {
+        ramp_ticks + (distance - ramp_distance) / cruise
+    }
+}
+
+// An approaching vehicle the plan gives an entry tick
+struct Candidate {
+    index: usize,
+    // Travelled when the front reaches the stop line
+    stop_line: f32,
+    // Still to drive to the stop line
+    distance: f32,
+    // Soonest tick it can reach the stop line, and how fast it is going if it does
+    earliest: f32,
+    velocity: f32,
+    // Candidate in front of it in its lane, which has to enter first
+    follows: Option<usize>,
+}
+
+struct Search<'a> {
+    vehicles: &'a [Vehicle],
+    candidates: Vec<Candidate>,
+    // Zones between pairs of vehicles by index, in pixels travelled since entering the window
+    zones: HashMap<(usize, usize), ConflictZone>,
+    // Vehicles over the stop line, whose times through the box are already fixed
+    committed: Vec<usize>,
+    // Travelled by each vehicle involved once the next one in its lane may enter
+    lane_clear: HashMap<usize, f32>,
+    max_wait: Option<f32>,
+    tick: f32,
+    nodes: usize,
+    best_delay: f32,
+    best_entries: Vec<f32>,
+}
+
+impl<'a> Search<'a> {
+    // The vehicles nearest the stop line as candidates, with those past the stop line fixed
+    fn new(
+        vehicles: &'a [Vehicle],
+        planned: &[usize],
+        conflicts: &ConflictTable,
+        max_wait: Option<f32>,
+        tick: u64
+    ) -> Self {
+        let now = tick as f32;
+        let candidates: Vec<Candidate> = planned
+            .iter()
+            .map(|&index| {
+                let car = &vehicles[index];
+                let distance = car.get_distance_to_stop_line() as f32;
+                let follows = planned
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &other)| {
+                        let other_car = &vehicles[other];
+                        other_car.origin == car.origin &&
+                            other_car.lane == car.lane &&
+                            other_car.travelled > car.travelled
+                    })
+                    .min_by_key(|(_, &other)| vehicles[other].travelled)
+                    .map(|(position, _)| position);
+                let (cruise, acceleration) = (car.kind.max_speed(), car.kind.acceleration());
+                Candidate {
+                    index,
+                    stop_line: (car.travelled as f32) + distance,
+                    distance,
+                    earliest: now + travel_ticks(distance, car.velocity, car.kind),
+                    velocity: (car.velocity.powi(2) + 2.0 * acceleration * distance)
+                        .sqrt()
+                        .min(cruise),
+                    follows,
+                }
+            })
+            .collect();
+        let committed: Vec<usize> = (0..vehicles.len())
+            .filter(|&i| {
+                vehicles[i].get_distance_to_stop_line() < 0 && !vehicles[i].is_in_end_lane()
+            })
+            .collect();
+        let zones = vehicle_zones(vehicles, planned, &committed, conflicts);
+        let lane_clear = planned
+            .iter()
+            .chain(&committed)
+            .map(|&index| (index, lane_clear_at(&vehicles[index])))
+            .collect();
+
+        Search {
+            vehicles,
+            candidates,
+            zones,
+            committed,
+            lane_clear,
+            max_wait,
+            tick: now,
+            nodes: 0,
+            best_delay: f32::MAX,
+            best_entries: vec![],
+        }
+    }
+
+    fn zone(&self, index: usize, other: usize) -> Option<ConflictZone> {
+        self.zones.get(&(index, other)).copied()
+    }
+
+    // A candidate entering later than it could slows down to reach the stop line at its slot,
+    // and one already waiting there pulls away at a crawl
+    fn line_velocity(&self, candidate: &Candidate, at: f32) -> f32 {
+        if at <= candidate.earliest + 1.0 {
+            return candidate.velocity;
+        }
+        (candidate.distance / (at - self.tick)).ceil().min(candidate.velocity).max(ENTRY_VELOCITY)
+    }
+
+    // Ticks after reaching the stop line at a tick at which a candidate gets to a zone and
+    // clears it. A zone reaching back along the approach, as lanes running side by side have,
+    // counts from the stop line, since the plan only decides who enters the box.
+    fn zone_ticks(&self, candidate: &Candidate, at: f32, zone: (u32, u32)) -> (f32, f32) {
+        let kind = self.vehicles[candidate.index].kind;
+        let velocity = self.line_velocity(candidate, at);
+        (
+            travel_ticks((zone.0 as f32) - candidate.stop_line, velocity, kind).max(0.0),
+            travel_ticks((zone.1 as f32) - candidate.stop_line, velocity, kind),
+        )
+    }
+
+    // Soonest a candidate can enter once its lane leader and everything placed before it has
+    // cleared every zone they share
+    fn earliest_entry(&self, position: usize, entries: &[Option<f32>]) -> f32 {
+        let candidate = &self.candidates[position];
+        let mut entry = candidate.earliest;
+
+        if let Some(leader) = candidate.follows {
+            let ahead = &self.candidates[leader];
+            if let Some(at) = entries[leader] {
+                let kind = self.vehicles[ahead.index].kind;
+                let clear = self.lane_clear[&ahead.index] - ahead.stop_line;
+                entry = entry.max(at + travel_ticks(clear, self.line_velocity(ahead, at), kind));
+            }
+        }
+
+        // Vehicles over the stop line in its lane, which the lane order no longer covers
+        let vehicle = &self.vehicles[candidate.index];
+        for &other in &self.committed {
+            let ahead = &self.vehicles[other];
+            let clear = self.lane_clear[&other] - (ahead.travelled as f32);
+            if ahead.origin == vehicle.origin && ahead.lane == vehicle.lane && clear > 0.0 {
+                entry = entry.max(self.tick + travel_ticks(clear, ahead.velocity, ahead.kind));
+            }
+        }
+
+        for &other in &self.committed {
+            let car = &self.vehicles[other];
+            if let Some(zone) = self.zone(candidate.index, other) {
+                let travelled = car.travelled as f32;
+                if travelled < (zone.other_exit as f32) {
+                    let to_exit = (zone.other_exit as f32) - travelled;
+                    let cleared = self.tick + travel_ticks(to_exit, car.velocity, car.kind);
+                    let (to_zone, _) = self.zone_ticks(candidate, entry, (zone.entry, zone.exit));
+                    entry = entry.max(cleared - to_zone);
+                }
+            }
+        }
+
+        for (placed, &other_entry) in entries.iter().enumerate() {
+            if let Some(other_entry) = other_entry {
+                let other = &self.candidates[placed];
+                if let Some(zone) = self.zone(candidate.index, other.index) {
+                    let other_zone = (zone.other_entry, zone.other_exit);
+                    let (_, cleared) = self.zone_ticks(other, other_entry, other_zone);
+                    let (to_zone, _) = self.zone_ticks(candidate, entry, (zone.entry, zone.exit));
+                    entry = entry.max(other_entry + cleared - to_zone);
+                }
+            }
+        }
+        entry
+    }
+
+    // Depth first over entry orders, dropping any partial order already delayed as much as
+    // the best complete one, since placing more vehicles can only add delay
+    fn branch(&mut self, entries: &mut Vec<Option<f32>>, delay: f32, placed: usize) {
+        if delay >= self.best_delay || self.nodes >= MAX_NODES {
+            return;
+        }
+        self.nodes += 1;
+        if placed == self.candidates.len() {
+            self.best_delay = delay;
+            self.best_entries = entries.iter().map(|e| e.unwrap_or(self.tick)).collect();
+            return;
+        }
+        // The soonest to arrive are tried first so the first complete order is first come
+        // first served
+        let mut next = self.placeable(entries);
+        next.sort_by(|&a, &b| self.candidates[a].earliest.total_cmp(&self.candidates[b].earliest));
+        for position in next {
+            let entry = self.earliest_entry(position, entries);
+            entries[position] = Some(entry);
+            let added = entry - self.candidates[position].earliest;
+            self.branch(entries, delay + added, placed + 1);
+            entries[position] = None;
+        }
+    }
+
+    // Candidates not yet placed whose lane leader is, only the overdue ones while any of those
+    // are left so the maximum wait holds whatever order is chosen
+    fn placeable(&self, entries: &[Option<f32>]) -> Vec<usize> {
+        let placeable: Vec<usize> = (0..self.candidates.len())
+            .filter(|&c| entries[c].is_none())
+            .filter(|&c| self.candidates[c].follows.is_none_or(|l| entries[l].is_some()))
+            .collect();
+        let overdue: Vec<usize> = placeable
+            .iter()
+            .copied()
+            .filter(|&c| is_overdue(&self.vehicles[self.candidates[c].index], self.max_wait))
+            .collect();
+        if overdue.is_empty() { placeable } else { overdue }
+    }
+
+    // Places the candidates one at a time, always the highest ranked one that can go next,
+    // and returns the delay
+    fn place_by_rank(&mut self, rank: impl Fn(&Vehicle) -> f32) -> f32 {
+        let mut entries = vec![None; self.candidates.len()];
+        let mut delay = 0.0;
+        for _ in 0..self.candidates.len() {
+            let next = self
+                .placeable(&entries)
+                .into_iter()
+                .max_by(|&a, &b| {
+                    let (a, b) = (self.candidates[a].index, self.candidates[b].index);
+                    rank(&self.vehicles[a]).total_cmp(&rank(&self.vehicles[b]))
+                });
+            if let Some(position) = next {
+                let entry = self.earliest_entry(position, &entries);
+                entries[position] = Some(entry);
+                delay += entry - self.candidates[position].earliest;
+            }
+        }
+        self.best_entries = entries.iter().map(|e| e.unwrap_or(self.tick)).collect();
+        delay
+    }
+
+    fn slots(&self) -> Vec<(usize, u64)> {
+        self.candidates
+            .iter()
+            .zip(&self.best_entries)
+            .map(|(candidate, entry)| (candidate.index, entry.ceil() as u64))
+            .collect()
+    }
+}
+
+// Approaching vehicles nearest the stop line, the rest losing any slot until a later plan
+fn planned_vehicles(vehicles: &mut [Vehicle]) -> Vec<usize> {
+    let mut approaching: Vec<usize> = (0..vehicles.len())
+        .filter(|&i| vehicles[i].get_distance_to_stop_line() >= 0)
+        .collect();
+    approaching.sort_by_key(|&i| vehicles[i].get_distance_to_stop_line());
+    for &index in approaching.iter().skip(PLANNED_VEHICLES) {
+        vehicles[index].slot = None;
+    }
+    approaching.truncate(PLANNED_VEHICLES);
+    approaching
+}
+
+// Gives the vehicles nearest the stop line the entry ticks that keep their total delay lowest,
+// with each conflict zone used by one vehicle at a time, every vehicle held to its
+// acceleration and cruising speed and overdue vehicles entering first. Returns the total
+// planned delay in ticks.
+pub fn plan_entries(
+    vehicles: &mut [Vehicle],
+    conflicts: &ConflictTable,
+    max_wait: Option<f32>,
+    tick: u64
+) -> f32 {
+    let planned = planned_vehicles(vehicles);
+    let mut search = Search::new(vehicles, &planned, conflicts, max_wait, tick);
+    let mut entries = vec![None; search.candidates.len()];
+    search.branch(&mut entries, 0.0, 0);
+
+    let delay = if search.best_entries.is_empty() { 0.0 } else { search.best_delay };
+    for (index, slot) in search.slots() {
+        vehicles[index].slot = Some(slot);
+    }
+    delay
+}
+
+// Gives the vehicles nearest the stop line entry ticks in order of rank, highest first, under
+// the same limits as plan_entries. Returns the total planned delay in ticks.
+pub fn plan_entries_by_rank(
+    vehicles: &mut [Vehicle],
+    conflicts: &ConflictTable,
+    max_wait: Option<f32>,
+    tick: u64,
+    rank: impl Fn(&Vehicle) -> f32
+) -> f32 {
+    let planned = planned_vehicles(vehicles);
+    let mut search = Search::new(vehicles, &planned, conflicts, max_wait, tick);
+    let delay = search.place_by_rank(rank);
+    for (index, slot) in search.slots() {
+        vehicles[index].slot = Some(slot);
+    }
+    delay
+}
+
+// Zones worked out from the vehicles' own paths, which are tighter than the table's zones as
+// those cover every kind that can make a movement. The table still rules out the pairs that
+// cannot meet, and the zone shared along a lane is left to the lane order and headway.
+fn vehicle_zones(
+    vehicles: &[Vehicle],
+    approaching: &[usize],
+    committed: &[usize],
+    conflicts: &ConflictTable
+) -> HashMap<(usize, usize), ConflictZone> {
+    let involved: Vec<usize> = approaching.iter().chain(committed).copied().collect();
+    let paths: HashMap<usize, Vec<Rect>> = involved
+        .iter()
+        .map(|&index| (index, remaining_path_segments(&vehicles[index])))
+        .collect();
+
+    let mut zones = HashMap::new();
+    for (position, &index) in approaching.iter().enumerate() {
+        for &other in involved.iter().skip(position + 1) {
+            let (car, other_car) = (&vehicles[index], &vehicles[other]);
+            if
+                (car.origin == other_car.origin && car.lane == other_car.lane) ||
+                !conflicts.may_meet(car, other_car)
+            {
+                continue;
+            }
+            if let Some(zone) = conflict_zone(&paths[&index], &paths[&other]) {
+                let zone = ConflictZone {
+                    entry: zone.entry + car.travelled,
+                    exit: zone.exit + car.travelled,
+                    other_entry: zone.other_entry + other_car.travelled,
+                    other_exit: zone.other_exit + other_car.travelled,
+                };
+                zones.insert((index, other), zone);
+                zones.insert((other, index), zone.swapped());
+            }
+        }
+    }
+    zones
+}
+
+// Travelled by a vehicle once the next one in its lane may cross the stop line: its length and
+// the safety gap past the line going straight on, but a turning vehicle's footprint swings
+// round across the lane, so then only once it is clear of the lane's way over the box
+fn lane_clear_at(vehicle: &Vehicle) -> f32 {
+    let stop_line = (vehicle.travelled as i32) + vehicle.get_distance_to_stop_line();
+    let headway = (stop_line + (vehicle.kind.length() as i32) + SAFETY_GAP) as f32;
+    if vehicle.direction == vehicle.origin.opposite() {
+        return headway;
+    }
+    let offset = approach_lane_offset(vehicle.driving_side, vehicle.origin, vehicle.lane);
+    let (third_width, third_height) = ((WINDOW_WIDTH / 3) as i32, (WINDOW_HEIGHT / 3) as i32);
+    let lane_across_box = match vehicle.origin {
+        Direction::North | Direction::South =>
+            Rect::new(offset, third_height, LANE_WIDTH, WINDOW_HEIGHT / 3),
+        Direction::East | Direction::West =>
+            Rect::new(third_width, offset, WINDOW_WIDTH / 3, LANE_WIDTH),
+    };
+    let mut driven = vehicle.clone();
+    driven.set_velocity(1.0);
+    while
+        driven.is_in_entire_intersection() &&
+        (driven.get_distance_to_stop_line() >= 0 ||
+            are_within_safety_gap(driven.get_rect(), lane_across_box))
+    {
+        driven.update(1.0);
+    }
+    (driven.travelled as f32).max(headway)
+}
+
+// Vehicles slow down on the approach to reach the stop line as their slot comes, and hold
+// there if they get to it early
+pub fn slot_velocity_limit(car: &Vehicle, tick: u64) -> f32 {
+    let distance = car.get_distance_to_stop_line();
+    match car.slot {
+        Some(slot) if distance >= 0 && slot > tick =>
+            ((distance as f32) / ((slot - tick) as f32)).ceil(),
+        _ => f32::MAX,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::LaneLayout;
+
+    // A car going straight on in the middle lane, its front the distance short of its stop line
+    fn car_short_of_stop_line(origin: Direction, distance: i32, id: u32) -> Vehicle {
+        let layout = LaneLayout::new();
+        let mut car =
+            Vehicle::new_in_lane(origin, origin.opposite(), VehicleKind::Car, &layout, 1, id);
+        let to_go = car.get_distance_to_stop_line() - distance;
+        match origin {
+            Direction::North => car.position.y += to_go,
+            Direction::South => car.position.y -= to_go,
+            Direction::East => car.position.x -= to_go,
+            Direction::West => car.position.x += to_go,
+        }
+        car.travelled = to_go as u32;
+        car.set_velocity(VehicleKind::Car.max_speed());
+        car
+    }
+
+    #[test]
+    fn crossing_car_waits_for_its_zone_to_clear() {
+        let conflicts = ConflictTable::new(&LaneLayout::new());
+        let mut alone = vec![car_short_of_stop_line(Direction::East, 0, 0)];
+        assert_eq!(plan_entries(&mut alone, &conflicts, None, 0), 0.0);
+        assert_eq!(alone[0].slot, Some(0));
+
+        // The cars' paths cross just past the north stop line but far along the east car's
+        // path, so the north car goes through when it gets there and the east car, already at
+        // its line, waits just long enough to reach the crossing after it
+        let mut vehicles = vec![
+            car_short_of_stop_line(Direction::East, 0, 0),
+            car_short_of_stop_line(Direction::North, 60, 1)
+        ];
+        let delay = plan_entries(&mut vehicles, &conflicts, None, 0);
+        assert_eq!((vehicles[0].slot, vehicles[1].slot), (Some(8), Some(20)));
+        assert!((delay - 8.0).abs() < 1e-3, "delay {}", delay);
+    }
+}
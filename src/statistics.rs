@@ -1,3 +1,5 @@
+use crate::conflict::Movement;
+
 pub struct Statistics {
     pub num_vehicles: u32,
     pub max_velocity: f32,
@@ -5,6 +7,21 @@ pub struct Statistics {
     pub max_time: f32,
     pub min_time: f32,
     pub close_calls: u32,
+    pub crashes: u32,
+    pub emergency_vehicles: u32,
+    pub emergency_total_delay: f32,
+    pub emergency_max_delay: f32,
+    pub pedestrians: u32,
+    pub pedestrian_total_wait: f32,
+    pub pedestrian_max_wait: f32,
+    // Credits paid for slots under the auction manager
+    pub auction_revenue: f32,
+    // Value of time finished vehicles lost to delay, payments only move credits around so
+    // welfare is this loss taken away
+    pub delay_cost: f32,
+    // Finished vehicles' delay by lane movement, as the movement, vehicles and total delay
+    pub movement_delays: Vec<(Movement, u32, f32)>,
+    pub max_wait: f32,
 }
 
 impl Statistics {
@@ -16,6 +33,17 @@ impl Statistics {
             max_time: 0.0,
             min_time: -1.0,
             close_calls: 0,
+            crashes: 0,
+            emergency_vehicles: 0,
+            emergency_total_delay: 0.0,
+            emergency_max_delay: 0.0,
+            pedestrians: 0,
+            pedestrian_total_wait: 0.0,
+            pedestrian_max_wait: 0.0,
+            auction_revenue: 0.0,
+            delay_cost: 0.0,
+            movement_delays: vec![],
+            max_wait: 0.0,
         }
     }
 
@@ -42,4 +70,121 @@ impl Statistics {
     pub fn add_close_call(&mut self, amount_to_add: u32) {
         self.close_calls += amount_to_add;
     }
+
+    pub fn add_crash(&mut self, amount_to_add: u32) {
+        self.crashes += amount_to_add;
+    }
+
+    pub fn add_emergency_delay(&mut self, delay: f32) {
+        self.emergency_vehicles += 1;
+        self.emergency_total_delay += delay;
+        self.emergency_max_delay = self.emergency_max_delay.max(delay);
+    }
+
+    pub fn average_emergency_delay(&self) -> f32 {
+        if self.emergency_vehicles == 0 {
+            return 0.0;
+        }
+        self.emergency_total_delay / (self.emergency_vehicles as f32)
+    }
+
+    pub fn add_pedestrian_wait(&mut self, wait_time: f32) {
+        self.pedestrians += 1;
+        self.pedestrian_total_wait += wait_time;
+        self.pedestrian_max_wait = self.pedestrian_max_wait.max(wait_time);
+    }
+
+    pub fn average_pedestrian_wait(&self) -> f32 {
+        if self.pedestrians == 0 {
+            return 0.0;
+        }
+        self.pedestrian_total_wait / (self.pedestrians as f32)
+    }
+
+    pub fn add_auction_payment(&mut self, price: f32) {
+        self.auction_revenue += price;
+    }
+
+    pub fn add_delay_cost(&mut self, cost: f32) {
+        self.delay_cost += cost;
+    }
+
+    pub fn welfare(&self) -> f32 {
+        -self.delay_cost
+    }
+
+    pub fn add_vehicle_delay(&mut self, movement: Movement, delay: f32) {
+        self.max_wait = self.max_wait.max(delay);
+        match self.movement_delays.iter_mut().find(|(m, _, _)| *m == movement) {
+            Some((_, vehicles, total)) => {
+                *vehicles += 1;
+                *total += delay;
+            }
+            None => self.movement_delays.push((movement, 1, delay)),
+        }
+    }
+
+    // Jain's index over the average delay of each movement, 1 when every movement is delayed
+    // alike, falling towards 1/n as one of n movements takes all the delay
+    pub fn fairness_index(&self) -> f32 {
+        let averages: Vec<f32> = self.movement_delays
+            .iter()
+            .map(|(_, vehicles, total)| total / (*vehicles as f32))
+            .collect();
+        let sum: f32 = averages.iter().sum();
+        let sum_of_squares: f32 = averages
+            .iter()
+            .map(|average| average * average)
+            .sum();
+        if sum_of_squares == 0.0 {
+            return 1.0;
+        }
+        (sum * sum) / ((averages.len() as f32) * sum_of_squares)
+    }
+
+    pub fn throughput_per_minute(&self, tick: u64) -> f32 {
+        // ticks run at 60Hz so there are 3600 in a simulated minute
+        if tick == 0 {
+            return 0.0;
+        }
+        (self.num_vehicles as f32) / ((tick as f32) / 3600.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intersection::Direction;
+
+    // The four straight on movements through the middle lanes
+    fn straights() -> Vec<Movement> {
+        [Direction::North, Direction::South, Direction::East, Direction::West]
+            .into_iter()
+            .map(|origin| Movement { origin, lane: 1, direction: origin.opposite() })
+            .collect()
+    }
+
+    #[test]
+    fn fairness_index_without_delays_is_fair() {
+        assert_eq!(Statistics::new().fairness_index(), 1.0);
+    }
+
+    #[test]
+    fn fairness_index_of_equal_delays_is_one() {
+        let mut stats = Statistics::new();
+        for movement in straights() {
+            stats.add_vehicle_delay(movement, 500.0);
+            stats.add_vehicle_delay(movement, 1500.0);
+        }
+        assert!((stats.fairness_index() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fairness_index_of_one_delayed_movement_is_one_over_n() {
+        let mut stats = Statistics::new();
+        for (index, movement) in straights().into_iter().enumerate() {
+            stats.add_vehicle_delay(movement, if index == 0 { 2000.0 } else { 0.0 });
+        }
+        assert!((stats.fairness_index() - 0.25).abs() < 1e-6);
+    }
 }
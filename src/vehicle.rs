@@ -1,108 +1,333 @@
-use sdl2::rect::Point;
+use std::{ collections::VecDeque, f32::consts::TAU, path::Path };
+
+use rand::Rng;
 
 use crate::{
+    geometry::{ Point, Rect },
     intersection::Direction,
     WINDOW_HEIGHT,
     WINDOW_WIDTH,
-    render::{ VERTICAL_LANE_WIDTH, HORIZONTAL_LANE_HEIGHT },
+    conflict::Movement,
+    auction::{ draw_value_of_time, STARTING_BUDGET },
+    layout::{
+        DrivingSide,
+        Junction,
+        LaneLayout,
+        LANE_WIDTH,
+        RING_RADIUS,
+        approach_lane_offset,
+        exit_lane_offset,
+        is_kerb_at_offset,
+        ring_angle,
+        ring_point,
+        ring_point_on_arm,
+    },
 };
 
-pub const VEHICLE_WIDTH: u32 = (VERTICAL_LANE_WIDTH * 3) / 2;
-pub const VEHICLE_HEIGHT: u32 = VERTICAL_LANE_WIDTH;
+pub const VEHICLE_WIDTH: u32 = (LANE_WIDTH * 3) / 2;
+pub const VEHICLE_HEIGHT: u32 = LANE_WIDTH;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VehicleKind {
+    Car,
+    Van,
+    Truck,
+    Bus,
+    Motorcycle,
+    Emergency,
+    Bicycle,
+}
+
+impl VehicleKind {
+    pub const ALL: [VehicleKind; 7] = [
+        VehicleKind::Car,
+        VehicleKind::Van,
+        VehicleKind::Truck,
+        VehicleKind::Bus,
+        VehicleKind::Motorcycle,
+        VehicleKind::Emergency,
+        VehicleKind::Bicycle,
+    ];
+
+    pub fn from_name(name: &str) -> Option<VehicleKind> {
+        VehicleKind::ALL.iter()
+            .find(|k| k.name() == name)
+            .copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VehicleKind::Car => "car",
+            VehicleKind::Van => "van",
+            VehicleKind::Truck => "truck",
+            VehicleKind::Bus => "bus",
+            VehicleKind::Motorcycle => "motorcycle",
+            VehicleKind::Emergency => "emergency",
+            VehicleKind::Bicycle => "bicycle",
+        }
+    }
+
+    // Size along the direction of travel
+    pub fn length(&self) -> u32 {
+        match self {
+            VehicleKind::Car | VehicleKind::Emergency => VEHICLE_WIDTH - 10,
+            VehicleKind::Van => VEHICLE_WIDTH - 4,
+            VehicleKind::Truck => VEHICLE_WIDTH * 2 - 30,
+            VehicleKind::Bus => VEHICLE_WIDTH * 2 - 20,
+            VehicleKind::Motorcycle => VEHICLE_WIDTH / 2 - 5,
+            VehicleKind::Bicycle => VEHICLE_WIDTH / 3,
+        }
+    }
+
+    // Size across the direction of travel
+    pub fn breadth(&self) -> u32 {
+        match self {
+            VehicleKind::Car | VehicleKind::Emergency => VEHICLE_HEIGHT - 10,
+            VehicleKind::Van => VEHICLE_HEIGHT - 8,
+            VehicleKind::Truck | VehicleKind::Bus => VEHICLE_HEIGHT - 6,
+            VehicleKind::Motorcycle => VEHICLE_HEIGHT / 3,
+            // Narrow enough to ride beside a car in the kerb lane with a safety gap to spare
+            VehicleKind::Bicycle => 5,
+        }
+    }
+
+    // Cruising velocity the algorithm aims for, pixels per tick
+    pub fn max_speed(&self) -> f32 {
+        match self {
+            VehicleKind::Car | VehicleKind::Van => 3.0,
+            VehicleKind::Truck | VehicleKind::Bus | VehicleKind::Bicycle => 2.0,
+            VehicleKind::Motorcycle | VehicleKind::Emergency => 4.0,
+        }
+    }
+
+    // Largest velocity increase per tick, slowing down is always immediate
+    pub fn acceleration(&self) -> f32 {
+        match self {
+            VehicleKind::Car | VehicleKind::Motorcycle | VehicleKind::Emergency => 3.0,
+            VehicleKind::Van | VehicleKind::Bicycle => 1.0,
+            VehicleKind::Truck | VehicleKind::Bus => 0.25,
+        }
+    }
+
+    // Bicycles only ride the kerb lane, so they can go straight on or turn along the kerb
+    pub fn can_make(&self, origin: Direction, direction: Direction, side: DrivingSide) -> bool {
+        *self != VehicleKind::Bicycle ||
+            direction == origin.opposite() ||
+            direction == side.kerb_turn(origin)
+    }
+
+    // Cars use the shared sprite sheet, the rest fall back to it when their sprite is missing.
+    // Sprites not in the assets yet give None, so no error is printed at every launch.
+    pub fn sprite_path(&self) -> Option<&'static str> {
+        let path = match self {
+            VehicleKind::Car => None,
+            VehicleKind::Van => Some("src/assets/vehicles/van.png"),
+            VehicleKind::Truck => Some("src/assets/vehicles/truck.png"),
+            VehicleKind::Bus => Some("src/assets/vehicles/bus.png"),
+            VehicleKind::Motorcycle => Some("src/assets/vehicles/motorcycle.png"),
+            VehicleKind::Emergency => Some("src/assets/vehicles/emergency.png"),
+            VehicleKind::Bicycle => Some("src/assets/vehicles/bicycle.png"),
+        };
+        path.filter(|path| Path::new(path).exists())
+    }
+}
+
+// Relative spawn weights per vehicle kind
+#[derive(Debug, Clone)]
+pub struct VehicleMix {
+    pub weights: Vec<(VehicleKind, u32)>,
+}
+
+impl VehicleMix {
+    pub fn new() -> Self {
+        Self {
+            weights: vec![(VehicleKind::Car, 1)],
+        }
+    }
+
+    // Only kinds accepted by `allowed` are considered, falling back to a car
+    pub fn pick<R: Rng>(&self, rng: &mut R, allowed: impl Fn(VehicleKind) -> bool) -> VehicleKind {
+        let weights: Vec<&(VehicleKind, u32)> = self.weights
+            .iter()
+            .filter(|(k, _)| allowed(*k))
+            .collect();
+        let total: u32 = weights
+            .iter()
+            .map(|(_, w)| w)
+            .sum();
+        if total == 0 {
+            return VehicleKind::Car;
+        }
+        let mut roll = rng.gen_range(0..total);
+        for (kind, weight) in weights {
+            if roll < *weight {
+                return *kind;
+            }
+            roll -= weight;
+        }
+        VehicleKind::Car
+    }
+}
+
+// How far through a roundabout a vehicle is, in the order it passes them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RingStage {
+    // Not yet at the give way line
+    Approaching,
+    // Past the give way line but not yet on the ring
+    Entering,
+    Circulating,
+    Leaving,
+}
 
 #[derive(Debug, Clone)]
 pub struct Vehicle {
     pub id: u32,
+    pub kind: VehicleKind,
     pub position: Point,
     pub width: u32,
     pub height: u32,
     pub time: f32,
     pub distance: f32,
+    // Pixels driven since entering the window, which conflict zones are measured in
+    pub travelled: u32,
+    // Tick the front of the vehicle came within ARRIVAL_DISTANCE of the stop line
+    pub arrival: Option<u64>,
+    // Whether the auction price was charged, which happens as the front crosses the stop line
+    pub paid: bool,
+    // Id of the vehicle that formed the platoon this one crosses with, its own when alone
+    pub platoon: u32,
+    // Tick the optimised manager plans for the front to reach the stop line
+    pub slot: Option<u64>,
+    // Credits per second of delay, what is left to bid with and the price of its latest slot
+    pub value_of_time: f32,
+    pub budget: f32,
+    pub price: f32,
+    // Latest command from an outside controller, the fastest it may go and whether its lane
+    // is held at the stop line
+    pub target_speed: Option<f32>,
+    pub held: bool,
     pub velocity: f32,
     pub origin: Direction,
     pub direction: Direction,
     pub facing: Direction,
+    // Approach lane counted from the kerb, and the lane it leaves the box by
+    pub lane: usize,
+    pub exit_lane: usize,
+    pub junction: Junction,
+    pub driving_side: DrivingSide,
     pub colliding: bool,
     pub close_calls: Vec<Vehicle>,
+    pub crashes: Vec<u32>,
+    // Exits still to take at the intersections after this one
+    pub route: VecDeque<Direction>,
+}
+
+// Where a vehicle enters an approach lane, and the way it faces there
+pub fn get_lane_position(
+    driving_side: DrivingSide,
+    origin: Direction,
+    lane: usize
+) -> (Point, Direction) {
+    let offset = approach_lane_offset(driving_side, origin, lane);
+    let position = match origin {
+        Direction::North => Point::new(offset, 0),
+        Direction::South => Point::new(offset, WINDOW_HEIGHT as i32),
+        Direction::East => Point::new(WINDOW_WIDTH as i32, offset),
+        Direction::West => Point::new(0, offset),
+    };
+    (position, origin.opposite())
 }
 
 impl Vehicle {
-    pub fn new(origin: Direction, direction: Direction, id: u32) -> Self {
-        let (mut x, mut y) = (0, 0);
-        let mut facing = Direction::North;
-
-        match (origin, direction) {
-            (Direction::South, Direction::North) => {
-                y = WINDOW_HEIGHT - VEHICLE_HEIGHT;
-                x = (WINDOW_WIDTH * 2) / 3 - VERTICAL_LANE_WIDTH * 2;
-                facing = Direction::North;
-            }
-            (Direction::South, Direction::East) => {
-                y = WINDOW_HEIGHT;
-                x = (WINDOW_WIDTH * 2) / 3 - VERTICAL_LANE_WIDTH;
-                facing = Direction::North;
-            }
-            (Direction::South, Direction::West) => {
-                y = WINDOW_HEIGHT;
-                x = (WINDOW_WIDTH * 2) / 3 - VERTICAL_LANE_WIDTH * 3;
-                facing = Direction::North;
-            }
-            (Direction::East, Direction::South) => {
-                x = WINDOW_WIDTH;
-                y = WINDOW_HEIGHT / 3 + HORIZONTAL_LANE_HEIGHT * 2;
-                facing = Direction::West;
-            }
-            (Direction::East, Direction::North) => {
-                x = WINDOW_WIDTH;
-                y = WINDOW_HEIGHT / 3;
-                facing = Direction::West;
-            }
-            (Direction::East, Direction::West) => {
-                x = WINDOW_WIDTH - VEHICLE_WIDTH;
-                y = WINDOW_HEIGHT / 3 + HORIZONTAL_LANE_HEIGHT;
-                facing = Direction::West;
-            }
-            (Direction::North, Direction::South) => {
-                x = WINDOW_WIDTH / 3 + VERTICAL_LANE_WIDTH;
-                facing = Direction::South;
-            }
-            (Direction::North, Direction::East) => {
-                x = WINDOW_WIDTH / 3 + VERTICAL_LANE_WIDTH * 2;
-                facing = Direction::South;
-            }
-            (Direction::North, Direction::West) => {
-                x = WINDOW_WIDTH / 3;
-                facing = Direction::South;
-            }
-            (Direction::West, Direction::North) => {
-                y = (WINDOW_HEIGHT * 2) / 3 - HORIZONTAL_LANE_HEIGHT * 3;
-                facing = Direction::East;
-            }
-            (Direction::West, Direction::East) => {
-                y = (WINDOW_HEIGHT * 2) / 3 - HORIZONTAL_LANE_HEIGHT * 2;
-                facing = Direction::East;
-            }
-            (Direction::West, Direction::South) => {
-                y = (WINDOW_HEIGHT * 2) / 3 - HORIZONTAL_LANE_HEIGHT;
-                facing = Direction::East;
+    // Picks one of the lanes the layout allows for this movement, bicycles keep to the kerb,
+    // and draws what the vehicle's time is worth to it
+    pub fn new<R: Rng>(
+        origin: Direction,
+        direction: Direction,
+        kind: VehicleKind,
+        layout: &LaneLayout,
+        id: u32,
+        rng: &mut R
+    ) -> Self {
+        let lanes = layout.legal_lanes(origin, direction);
+        let kerb_side = kind == VehicleKind::Bicycle && layout.junction == Junction::Cross;
+        let lane = if kerb_side || lanes.is_empty() {
+            0
+        } else {
+            lanes[rng.gen_range(0..lanes.len())]
+        };
+        let mut vehicle = Self::new_in_lane(origin, direction, kind, layout, lane, id);
+        vehicle.value_of_time = draw_value_of_time(rng);
+        vehicle
+    }
+
+    // Value of time is left at nothing for the caller to draw
+    pub fn new_in_lane(
+        origin: Direction,
+        direction: Direction,
+        kind: VehicleKind,
+        layout: &LaneLayout,
+        lane: usize,
+        id: u32
+    ) -> Self {
+        let (mut position, facing) = get_lane_position(layout.driving_side, origin, lane);
+        let breadth = kind.breadth() as i32;
+        let kerb_at_offset = is_kerb_at_offset(layout.driving_side, origin);
+
+        // Bicycles ride along the kerb edge of the kerb lane whatever their movement, other
+        // vehicles keep to the offside of it so a bicycle can pass on the inside
+        let shift = if kind == VehicleKind::Bicycle && layout.junction == Junction::Cross {
+            !kerb_at_offset
+        } else {
+            lane == 0 && kerb_at_offset
+        };
+        if shift {
+            match origin {
+                Direction::North | Direction::South => {
+                    position.x += (LANE_WIDTH as i32) - breadth;
+                }
+                Direction::East | Direction::West => {
+                    position.y += (LANE_WIDTH as i32) - breadth;
+                }
             }
-            _ => {}
         }
 
-        Self {
+        let mut vehicle = Self {
             id,
-            position: Point::new(x as i32, y as i32),
-            width: VEHICLE_WIDTH - 10,
-            height: VEHICLE_HEIGHT - 10,
+            kind,
+            position,
+            width: kind.length(),
+            height: kind.breadth(),
             time: 0.0,
             distance: 0.0,
+            travelled: 0,
+            arrival: None,
+            paid: false,
+            platoon: id,
+            slot: None,
+            value_of_time: 0.0,
+            budget: STARTING_BUDGET,
+            price: 0.0,
+            target_speed: None,
+            held: false,
             velocity: 1.0,
             origin,
             direction,
             facing,
+            lane,
+            exit_lane: layout
+                .exit_lane(origin, direction, lane)
+                .expect("vehicle leaving by a missing arm"),
+            junction: layout.junction,
+            driving_side: layout.driving_side,
             colliding: false,
             close_calls: vec![],
-        }
+            crashes: vec![],
+            route: VecDeque::new(),
+        };
+        vehicle.set_dimensions(facing);
+        vehicle
     }
 
     pub fn update(&mut self, delta_time: f32) {
@@ -114,15 +339,63 @@ impl Vehicle {
         self.update_position();
     }
 
+    // Width and height are screen extents, so they swap with the direction the vehicle faces
+    fn set_dimensions(&mut self, facing: Direction) {
+        match facing {
+            Direction::North | Direction::South => {
+                self.width = self.kind.breadth();
+                self.height = self.kind.length();
+            }
+            Direction::East | Direction::West => {
+                self.width = self.kind.length();
+                self.height = self.kind.breadth();
+            }
+        }
+    }
+
+    pub fn limit_acceleration(&self, previous_velocity: f32, velocity: f32) -> f32 {
+        if velocity > previous_velocity {
+            velocity.min(previous_velocity + self.kind.acceleration())
+        } else {
+            velocity
+        }
+    }
+
+    // Time lost compared to covering the same distance at cruising speed
+    pub fn get_delay(&self) -> f32 {
+        (self.time - self.distance / self.kind.max_speed()).max(0.0)
+    }
+
     pub fn set_velocity(&mut self, velocity: f32) {
         // Set the velocity of the vehicle
         self.velocity = velocity;
     }
 
+    pub fn movement(&self) -> Movement {
+        Movement { origin: self.origin, lane: self.lane, direction: self.direction }
+    }
+
+    pub fn get_rect(&self) -> Rect {
+        Rect::new(self.position.x, self.position.y, self.width, self.height)
+    }
+
+    pub fn get_centre(&self) -> Point {
+        Point::new(
+            self.position.x + (self.width as i32) / 2,
+            self.position.y + (self.height as i32) / 2
+        )
+    }
+
     pub fn is_in_end_lane(&self) -> bool {
+        // A bicycle at the kerb edge rides along the edge of the box it will leave by before it
+        // turns into it
+        if self.junction == Junction::Cross && !self.has_reached_turning_point() {
+            return false;
+        }
+        // Leaving north or west the footprint trails back into the box from the position
         match self.direction {
             Direction::North => {
-                return self.position.y <= (WINDOW_HEIGHT as i32) / 3;
+                return self.position.y + (self.height as i32) <= (WINDOW_HEIGHT as i32) / 3;
             }
             Direction::South => {
                 return self.position.y >= ((WINDOW_HEIGHT * 2) as i32) / 3;
@@ -131,7 +404,7 @@ impl Vehicle {
                 return self.position.x >= ((WINDOW_WIDTH * 2) as i32) / 3;
             }
             Direction::West => {
-                return self.position.x <= (WINDOW_WIDTH as i32) / 3;
+                return self.position.x + (self.width as i32) <= (WINDOW_WIDTH as i32) / 3;
             }
         }
     }
@@ -143,34 +416,62 @@ impl Vehicle {
             self.position.y <= ((WINDOW_HEIGHT as i32) * 2) / 3
     }
 
+    pub fn is_approaching(&self) -> bool {
+        match self.origin {
+            Direction::North => self.position.y < (WINDOW_HEIGHT as i32) / 3,
+            Direction::South => self.position.y > ((WINDOW_HEIGHT as i32) * 2) / 3,
+            Direction::East => self.position.x > ((WINDOW_WIDTH as i32) * 2) / 3,
+            Direction::West => self.position.x < (WINDOW_WIDTH as i32) / 3,
+        }
+    }
+
+    // Pixels between the front of the vehicle and the edge of the box, negative once past it
+    pub fn get_distance_to_stop_line(&self) -> i32 {
+        match self.origin {
+            Direction::North =>
+                (WINDOW_HEIGHT as i32) / 3 - (self.position.y + (self.height as i32)),
+            Direction::South => self.position.y - ((WINDOW_HEIGHT as i32) * 2) / 3,
+            Direction::East => self.position.x - ((WINDOW_WIDTH as i32) * 2) / 3,
+            Direction::West =>
+                (WINDOW_WIDTH as i32) / 3 - (self.position.x + (self.width as i32)),
+        }
+    }
+
     pub fn is_in_entire_intersection(&self) -> bool {
         self.position.x <= (WINDOW_WIDTH as i32) &&
-            self.position.x >= 0 - (VEHICLE_WIDTH as i32) &&
+            self.position.x >= 0 - (self.width as i32) &&
             self.position.y <= (WINDOW_HEIGHT as i32) &&
-            self.position.y >= 0 - (VEHICLE_HEIGHT as i32)
+            self.position.y >= 0 - (self.height as i32)
     }
 
     fn update_position(&mut self) {
+        self.travelled += self.velocity as u32;
+        if self.junction == Junction::Roundabout {
+            let (centre, facing) = self.roundabout_step(&self.position);
+            self.facing = facing;
+            self.set_dimensions(facing);
+            self.position = Point::new(
+                (centre.0 - (self.width as f32) / 2.0).round() as i32,
+                (centre.1 - (self.height as f32) / 2.0).round() as i32
+            );
+            return;
+        }
         if !self.has_reached_turning_point() {
             match self.origin {
                 Direction::North => {
-                    self.width = VEHICLE_HEIGHT - 10;
-                    self.height = VEHICLE_WIDTH - 10;
+                    self.set_dimensions(self.facing);
                     self.position.y += self.velocity as i32;
                 }
                 Direction::South => {
-                    self.width = VEHICLE_HEIGHT - 10;
-                    self.height = VEHICLE_WIDTH - 10;
+                    self.set_dimensions(self.facing);
                     self.position.y -= self.velocity as i32;
                 }
                 Direction::East => {
-                    self.width = VEHICLE_WIDTH - 10;
-                    self.height = VEHICLE_HEIGHT - 10;
+                    self.set_dimensions(self.facing);
                     self.position.x -= self.velocity as i32;
                 }
                 Direction::West => {
-                    self.width = VEHICLE_WIDTH - 10;
-                    self.height = VEHICLE_HEIGHT - 10;
+                    self.set_dimensions(self.facing);
                     self.position.x += self.velocity as i32;
                 }
             }
@@ -178,33 +479,55 @@ impl Vehicle {
             match self.direction {
                 Direction::North => {
                     self.facing = Direction::North;
-                    self.width = VEHICLE_HEIGHT - 10;
-                    self.height = VEHICLE_WIDTH - 10;
+                    self.set_dimensions(self.facing);
                     self.position.y -= self.velocity as i32;
                 }
                 Direction::South => {
                     self.facing = Direction::South;
-                    self.width = VEHICLE_HEIGHT - 10;
-                    self.height = VEHICLE_WIDTH - 10;
+                    self.set_dimensions(self.facing);
                     self.position.y += self.velocity as i32;
                 }
                 Direction::East => {
                     self.facing = Direction::East;
-                    self.width = VEHICLE_WIDTH - 10;
-                    self.height = VEHICLE_HEIGHT - 10;
+                    self.set_dimensions(self.facing);
                     self.position.x += self.velocity as i32;
                 }
                 Direction::West => {
                     self.facing = Direction::West;
-                    self.width = VEHICLE_WIDTH - 10;
-                    self.height = VEHICLE_HEIGHT - 10;
+                    self.set_dimensions(self.facing);
                     self.position.x -= self.velocity as i32;
                 }
             }
         }
     }
 
+    // Footprint at a position on the vehicle's path, turned with its length along its exit once
+    // it has reached the turning point there. On a roundabout it keeps its current footprint.
+    pub fn footprint_at(&self, position: Point) -> Rect {
+        let facing = if self.junction == Junction::Roundabout {
+            self.facing
+        } else if self.has_reached_future_turning_point(&position) {
+            self.direction
+        } else {
+            self.origin.opposite()
+        };
+        let (length, breadth) = (self.kind.length(), self.kind.breadth());
+        let (x, y) = (position.x, position.y);
+        match facing {
+            Direction::North | Direction::South => Rect::new(x, y, breadth, length),
+            Direction::East | Direction::West => Rect::new(x, y, length, breadth),
+        }
+    }
+
     pub fn get_future_position(&self, position: &Point) -> Point {
+        if self.junction == Junction::Roundabout {
+            // Predictions keep the current footprint even where the real vehicle would turn
+            let (centre, _) = self.roundabout_step(position);
+            return Point::new(
+                (centre.0 - (self.width as f32) / 2.0).round() as i32,
+                (centre.1 - (self.height as f32) / 2.0).round() as i32
+            );
+        }
         let mut future_pos = Point::new(position.x, position.y);
         if !self.has_reached_future_turning_point(&future_pos) {
             match self.origin {
@@ -240,122 +563,177 @@ impl Vehicle {
         future_pos
     }
 
+    // Lines the vehicle's centre follows onto and off the ring
+    fn ring_entry(&self) -> (f32, f32) {
+        let across =
+            (approach_lane_offset(self.driving_side, self.origin, self.lane) as f32) +
+            (self.kind.breadth() as f32) / 2.0;
+        ring_point_on_arm(self.origin, across)
+    }
+
+    fn ring_exit(&self) -> (f32, f32) {
+        let across =
+            (exit_lane_offset(self.driving_side, self.direction, self.exit_lane) as f32) +
+            (self.kind.breadth() as f32) / 2.0;
+        ring_point_on_arm(self.direction, across)
+    }
+
+    // Angle around the ring from the entry to the exit, in the driving side's direction
+    fn ring_sweep(&self) -> f32 {
+        let (entry_x, entry_y) = self.ring_entry();
+        let (exit_x, exit_y) = self.ring_exit();
+        let turned = ring_angle(exit_x, exit_y) - ring_angle(entry_x, entry_y);
+        (turned * self.driving_side.ring_turn()).rem_euclid(TAU)
+    }
+
+    // Angle driven on the ring so far by a vehicle centred at this point
+    fn ring_travelled(&self, centre: (f32, f32)) -> f32 {
+        let (entry_x, entry_y) = self.ring_entry();
+        let turned = ring_angle(centre.0, centre.1) - ring_angle(entry_x, entry_y);
+        (turned * self.driving_side.ring_turn()).rem_euclid(TAU)
+    }
+
+    fn is_before_ring(&self, centre: (f32, f32)) -> bool {
+        let (entry_x, entry_y) = self.ring_entry();
+        match self.origin {
+            Direction::North => centre.1 < entry_y,
+            Direction::South => centre.1 > entry_y,
+            Direction::East => centre.0 > entry_x,
+            Direction::West => centre.0 < entry_x,
+        }
+    }
+
+    pub fn ring_stage(&self) -> RingStage {
+        let centre = self.get_centre();
+        let centre = (centre.x as f32, centre.y as f32);
+        if self.get_distance_to_stop_line() >= 0 {
+            RingStage::Approaching
+        } else if self.is_before_ring(centre) {
+            RingStage::Entering
+        } else if self.ring_travelled(centre) < self.ring_sweep() {
+            RingStage::Circulating
+        } else {
+            RingStage::Leaving
+        }
+    }
+
+    // Centre and facing one tick on from a position on a roundabout path: along the approach,
+    // round the ring the way the driving side goes, then straight out along the exit lane
+    fn roundabout_step(&self, position: &Point) -> ((f32, f32), Direction) {
+        let step = (self.velocity as i32) as f32;
+        let centre = (
+            (position.x as f32) + (self.width as f32) / 2.0,
+            (position.y as f32) + (self.height as f32) / 2.0,
+        );
+
+        if self.is_before_ring(centre) {
+            let facing = self.origin.opposite();
+            return (step_towards(centre, facing, step), facing);
+        }
+
+        if self.ring_travelled(centre) < self.ring_sweep() {
+            let turn = self.driving_side.ring_turn();
+            let angle = ring_angle(centre.0, centre.1) + (turn * step) / RING_RADIUS;
+            // Screen direction of travel round the ring
+            let (dx, dy) = (-angle.sin() * turn, -angle.cos() * turn);
+            let facing = if dx.abs() > dy.abs() {
+                if dx > 0.0 { Direction::East } else { Direction::West }
+            } else if dy > 0.0 {
+                Direction::South
+            } else {
+                Direction::North
+            };
+            return (ring_point(angle), facing);
+        }
+
+        let (exit_x, exit_y) = self.ring_exit();
+        let on_exit_lane = match self.direction {
+            Direction::North | Direction::South => (exit_x, centre.1),
+            Direction::East | Direction::West => (centre.0, exit_y),
+        };
+        (step_towards(on_exit_lane, self.direction, step), self.direction)
+    }
+
+    pub fn get_predicted_path(&self, steps: usize) -> Vec<Point> {
+        let mut path = vec![];
+        let mut position = self.position;
+        for _ in 0..steps {
+            position = self.get_future_position(&position);
+            path.push(position);
+        }
+        path
+    }
+
     pub fn has_reached_turning_point(&self) -> bool {
-        match (self.origin, self.direction) {
-            (Direction::North, Direction::South) => true,
-            (Direction::North, Direction::East) =>
-                self.position.y >= (((WINDOW_HEIGHT * 2) / 3 - HORIZONTAL_LANE_HEIGHT * 3) as i32),
-            (Direction::North, Direction::West) => self.position.y >= ((WINDOW_HEIGHT / 3) as i32),
-            (Direction::South, Direction::North) => true,
-            (Direction::South, Direction::East) =>
-                self.position.y <= (((WINDOW_HEIGHT * 2) / 3 - HORIZONTAL_LANE_HEIGHT) as i32),
-            (Direction::South, Direction::West) =>
-                self.position.y <= ((WINDOW_HEIGHT / 3 + HORIZONTAL_LANE_HEIGHT * 2) as i32),
-            (Direction::East, Direction::North) =>
-                self.position.x <= (((WINDOW_WIDTH * 2) / 3 - VERTICAL_LANE_WIDTH) as i32),
-            (Direction::East, Direction::South) =>
-                self.position.x <= ((WINDOW_WIDTH / 3 + VERTICAL_LANE_WIDTH * 2) as i32),
-            (Direction::East, Direction::West) => true,
-            (Direction::West, Direction::North) =>
-                self.position.x >= (((WINDOW_WIDTH * 2) / 3 - VERTICAL_LANE_WIDTH * 3) as i32),
-            (Direction::West, Direction::South) => self.position.x >= ((WINDOW_WIDTH / 3) as i32),
-            (Direction::West, Direction::East) => true,
-            _ => true,
+        self.has_reached_future_turning_point(&self.position)
+    }
+
+    // Where a turning vehicle's path meets its exit lane, along the axis it approaches on
+    fn turning_point(&self) -> Option<i32> {
+        if self.direction == self.origin.opposite() {
+            return None;
         }
+        Some(exit_lane_offset(self.driving_side, self.direction, self.exit_lane))
     }
 
     fn has_reached_future_turning_point(&self, position: &Point) -> bool {
-        match (self.origin, self.direction) {
-            (Direction::North, Direction::South) => true,
-            (Direction::North, Direction::East) =>
-                position.y >= (((WINDOW_HEIGHT * 2) / 3 - HORIZONTAL_LANE_HEIGHT * 3) as i32),
-            (Direction::North, Direction::West) => position.y >= ((WINDOW_HEIGHT / 3) as i32),
-            (Direction::South, Direction::North) => true,
-            (Direction::South, Direction::East) =>
-                position.y <= (((WINDOW_HEIGHT * 2) / 3 - HORIZONTAL_LANE_HEIGHT) as i32),
-            (Direction::South, Direction::West) =>
-                position.y <= ((WINDOW_HEIGHT / 3 + HORIZONTAL_LANE_HEIGHT * 2) as i32),
-            (Direction::East, Direction::North) =>
-                position.x <= (((WINDOW_WIDTH * 2) / 3 - VERTICAL_LANE_WIDTH) as i32),
-            (Direction::East, Direction::South) =>
-                position.x <= ((WINDOW_WIDTH / 3 + VERTICAL_LANE_WIDTH * 2) as i32),
-            (Direction::East, Direction::West) => true,
-            (Direction::West, Direction::North) =>
-                position.x >= (((WINDOW_WIDTH * 2) / 3 - VERTICAL_LANE_WIDTH * 3) as i32),
-            (Direction::West, Direction::South) => position.x >= ((WINDOW_WIDTH / 3) as i32),
-            (Direction::West, Direction::East) => true,
-            _ => true,
+        match self.turning_point() {
+            None => true,
+            Some(turn) =>
+                match self.origin {
+                    Direction::North => position.y >= turn,
+                    Direction::South => position.y <= turn,
+                    Direction::East => position.x <= turn,
+                    Direction::West => position.x >= turn,
+                }
         }
     }
 
     pub fn get_distance_to_finish(&self) -> u32 {
-        let mut dx: i32 = 0;
-        let mut dy: i32 = 0;
-
-        match (self.origin, self.direction) {
-            (Direction::North, Direction::South) => {
-                dy = ((WINDOW_HEIGHT as i32) * 2) / 3 - self.position.y;
-            }
-            (Direction::North, Direction::East) => {
-                dx = ((WINDOW_WIDTH as i32) * 2) / 3 - self.position.x;
-                dy =
-                    ((WINDOW_HEIGHT as i32) * 2) / 3 -
-                    (HORIZONTAL_LANE_HEIGHT as i32) * 3 -
-                    self.position.y;
-            }
-            (Direction::North, Direction::West) => {
-                dx = self.position.x - (WINDOW_WIDTH as i32) / 3;
-                dy = self.position.y - (WINDOW_HEIGHT as i32) / 3;
-            }
-            (Direction::South, Direction::North) => {
-                dy = self.position.y - (WINDOW_HEIGHT as i32) / 3;
-            }
-            (Direction::South, Direction::East) => {
-                dx = ((WINDOW_WIDTH as i32) * 2) / 3 - self.position.x;
-                dy =
-                    self.position.y -
-                    ((WINDOW_HEIGHT as i32) * 2) / 3 -
-                    (HORIZONTAL_LANE_HEIGHT as i32);
-            }
-            (Direction::South, Direction::West) => {
-                dx = self.position.x - (WINDOW_WIDTH as i32) / 3;
-                dy =
-                    self.position.y -
-                    (WINDOW_HEIGHT as i32) / 3 +
-                    (HORIZONTAL_LANE_HEIGHT as i32) * 2;
-            }
-            (Direction::East, Direction::North) => {
-                dx =
-                    self.position.x -
-                    ((WINDOW_WIDTH as i32) * 2) / 3 -
-                    (VERTICAL_LANE_WIDTH as i32);
-                dy = self.position.y - (WINDOW_HEIGHT as i32) / 3;
-            }
-            (Direction::East, Direction::South) => {
-                dx = self.position.x - (WINDOW_WIDTH as i32) / 3 + (VERTICAL_LANE_WIDTH as i32) * 2;
-                dy = ((WINDOW_HEIGHT as i32) * 2) / 3 - self.position.y;
-            }
-            (Direction::East, Direction::West) => {
-                dx = self.position.x - (WINDOW_WIDTH as i32) / 3;
-            }
-            (Direction::West, Direction::North) => {
-                dx =
-                    ((WINDOW_WIDTH as i32) * 2) / 3 -
-                    (VERTICAL_LANE_WIDTH as i32) * 3 -
-                    self.position.x;
-                dy = self.position.y - (WINDOW_HEIGHT as i32) / 3;
-            }
-            (Direction::West, Direction::South) => {
-                dx = (WINDOW_WIDTH as i32) / 3 - self.position.x;
-                dy = ((WINDOW_HEIGHT as i32) * 2) / 3 - self.position.y;
-            }
-            (Direction::West, Direction::East) => {
-                dx = ((WINDOW_WIDTH as i32) * 2) / 3 - self.position.x;
-            }
-            _ => (),
+        if self.junction == Junction::Roundabout {
+            return self.get_roundabout_distance_to_finish();
         }
-        (dx.abs() + dy.abs()) as u32
+        // Edge of the box the vehicle leaves by, on the axis it leaves along
+        let exit_edge = match self.direction {
+            Direction::North => (WINDOW_HEIGHT as i32) / 3,
+            Direction::South => ((WINDOW_HEIGHT as i32) * 2) / 3,
+            Direction::East => ((WINDOW_WIDTH as i32) * 2) / 3,
+            Direction::West => (WINDOW_WIDTH as i32) / 3,
+        };
+        let (along, across) = match self.origin {
+            Direction::North | Direction::South => (self.position.y, self.position.x),
+            Direction::East | Direction::West => (self.position.x, self.position.y),
+        };
+        let distance = match self.turning_point() {
+            None => (exit_edge - along).abs(),
+            Some(turn) => (turn - along).abs() + (exit_edge - across).abs(),
+        };
+        distance as u32
+    }
+
+    fn get_roundabout_distance_to_finish(&self) -> u32 {
+        let centre = self.get_centre();
+        let centre = (centre.x as f32, centre.y as f32);
+        let sweep = self.ring_sweep();
+        let distance = if self.is_before_ring(centre) {
+            let (entry_x, entry_y) = self.ring_entry();
+            (entry_x - centre.0).abs() + (entry_y - centre.1).abs() + RING_RADIUS * sweep
+        } else if self.ring_travelled(centre) < sweep {
+            RING_RADIUS * (sweep - self.ring_travelled(centre))
+        } else {
+            0.0
+        };
+        distance as u32
     }
 
     // Add more methods as needed for vehicle-specific behavior
 }
+
+fn step_towards(point: (f32, f32), facing: Direction, step: f32) -> (f32, f32) {
+    match facing {
+        Direction::North => (point.0, point.1 - step),
+        Direction::South => (point.0, point.1 + step),
+        Direction::East => (point.0 + step, point.1),
+        Direction::West => (point.0 - step, point.1),
+    }
+}